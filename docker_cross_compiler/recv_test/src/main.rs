@@ -21,7 +21,10 @@ use std::time::Duration;
 
 use nix::sys::{socket::recv as nix_recv, socket::recvfrom, socket::send, socket::MsgFlags};
 
-const NETWORK_IFACE: &str = "eth0";
+// Used when neither --iface nor SLAC_IFACE name the PLC interface
+const DEFAULT_NETWORK_IFACE: &str = "eth0";
+const NETWORK_IFACE_ENV: &str = "SLAC_IFACE";
+const USAGE: &str = "usage: recv_test [-i|--iface <interface>]";
 
 const CM_SET_KEY_TYPE: u8 = b'\x01';
 //According to 15118-3 this value should be 0x00 and not 0xAA
@@ -36,6 +39,7 @@ const CM_SET_CCO_CAPAB: u8 = b'\x00';
 const ETH_P_ALL: u16 = 0x0003;
 const ETH_P_ARP: u16 = 0x0806; // from if_ether.h for SOCK_RAW
 const SIOCGIFADDR: ioctl_request_t = 0x8915;
+const SIOCGIFFLAGS: ioctl_request_t = 0x8913;
 const SIOCGIFINDEX: ioctl_request_t = 0x8933;
 const IFNAMSIZ: usize = 16; // net/if.h
 const SIOCGIFHWADDR: ioctl_request_t = 0x8927;
@@ -136,6 +140,14 @@ struct ifreq_ifindex {
     ifr_ifindex: libc::c_int,
 }
 
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct ifreq_ifflags {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_flags: libc::c_short,
+    _pad: [u8; 22],
+}
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 struct ifreq_ifhwaddr {
@@ -143,7 +155,79 @@ struct ifreq_ifhwaddr {
     ifr_ifhwaddr: libc::sockaddr,
 }
 
+// write() would silently cut the name at IFNAMSIZ bytes, the kernel refuses
+// '/', ':' and whitespace in interface names
+fn check_ifname(ifname: &str) -> Result<(), SocketError> {
+    let valid = !ifname.is_empty()
+        && ifname.len() < IFNAMSIZ
+        && ifname != "."
+        && ifname != ".."
+        && !ifname
+            .chars()
+            .any(|c| c == '/' || c == ':' || c.is_whitespace());
+    if !valid {
+        return Err(SocketError {
+            action: "checking interface name",
+            err: errno::Errno(libc::EINVAL),
+        });
+    }
+    Ok(())
+}
+
+/// Interface from `-i`/`--iface`, then from $SLAC_IFACE, then the default.
+/// Parses the arguments just like the slac binary does.
+fn network_iface(mut args: impl Iterator<Item = String>) -> Result<String, String> {
+    let mut iface = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--iface" => match args.next() {
+                Some(name) => iface = Some(name),
+                None => return Err(format!("{} needs an interface name", arg)),
+            },
+            _ => match arg.strip_prefix("--iface=") {
+                Some(name) => iface = Some(name.to_string()),
+                None => return Err(format!("unexpected argument {:?}", arg)),
+            },
+        }
+    }
+    Ok(iface
+        .or_else(|| std::env::var(NETWORK_IFACE_ENV).ok())
+        .unwrap_or_else(|| DEFAULT_NETWORK_IFACE.to_string()))
+}
+
+// Binding to an interface that is down works, but nothing is ever received
+fn check_iface_up(ifname: &str, sock: libc::c_int) -> Result<(), SocketError> {
+    check_ifname(ifname)?;
+    let mut ifr = ifreq_ifflags {
+        ifr_name: [0; IFNAMSIZ],
+        ifr_flags: 0,
+        _pad: [0; 22],
+    };
+    ifr.ifr_name.as_mut().write(ifname.as_bytes()).unwrap();
+    let res = unsafe { libc::ioctl(sock, SIOCGIFFLAGS.try_into().unwrap(), &mut ifr) };
+    sockerr!("getting interface flags", res);
+    if libc::c_int::from(ifr.ifr_flags) & libc::IFF_UP == 0 {
+        return Err(SocketError {
+            action: "checking the interface is up",
+            err: errno::Errno(libc::ENETDOWN),
+        });
+    }
+    Ok(())
+}
+
+// Reports what failed on which interface and exits, instead of a panic
+fn or_exit<T>(res: Result<T, SocketError>, ifname: &str) -> T {
+    match res {
+        Ok(value) => value,
+        Err(why) => {
+            eprintln!("Error {} on {}: {}", why.action, ifname, why.err);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn ifhwaddr_from_ifname(ifname: &str, sock: libc::c_int) -> Result<MacAddr, SocketError> {
+    check_ifname(ifname)?;
     let mut ifr = ifreq_ifhwaddr {
         ifr_name: [0; IFNAMSIZ],
         ifr_ifhwaddr: unsafe { std::mem::zeroed() },
@@ -159,6 +243,7 @@ fn ifhwaddr_from_ifname(ifname: &str, sock: libc::c_int) -> Result<MacAddr, Sock
 }
 
 fn ifindex_from_ifname(ifname: &str, sock: libc::c_int) -> Result<libc::c_int, SocketError> {
+    check_ifname(ifname)?;
     let mut ifr = ifreq_ifindex {
         ifr_name: [0; IFNAMSIZ],
         ifr_ifindex: 0,
@@ -223,57 +308,73 @@ pub fn bind_to_iface(socket: i32, ifindex: i32) -> Result<String, String> {
 pub fn rcv_frame(socket: i32) {
     loop {
         unsafe {
-            let ifindex = ifindex_from_ifname(NETWORK_IFACE, socket).unwrap();
-
-            let mut listen_sockaddr = libc::sockaddr_ll {
-                sll_family: libc::AF_PACKET as u16,
-                sll_protocol: ETH_P_ALL.to_be(),
-                sll_ifindex: ifindex,
-                sll_hatype: 0,
-                sll_pkttype: 0,
-                sll_halen: 0,
-                sll_addr: [0; 8],
-            };
-
             let mut buf: [u8; 1024] = [0; 1024];
-            let mut len: usize = 0;
+            // Filled in by the kernel with the sender of the frame
+            let mut sender: libc::sockaddr_ll = mem::zeroed();
+            let mut sender_len = mem::size_of_val(&sender) as libc::socklen_t;
             println!("Start waiting for a frame...");
-            let addr_ptr = &mut listen_sockaddr as *mut libc::sockaddr_ll as *mut libc::sockaddr;
-            let len_i: isize;
-            //len_i = libc::recvfrom(
-            //socket,
-            //buf.as_mut_ptr() as *mut libc::c_void,
-            //buf.len(),
-            //0,
-            //addr_ptr,
-            //mem::size_of_val(&listen_sockaddr) as *mut libc::socklen_t,
-            //);
-
-            len = match libc::recv(socket, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) {
-                d if d < 0 => {println!("Error receiving");d as usize}
-                len => {println!("Received {:?} bytes", len);println!("Buffer content: {:x?}", &buf[..len as usize]);len as usize}
-            };
-
+            let addr_ptr = &mut sender as *mut libc::sockaddr_ll as *mut libc::sockaddr;
+            match libc::recvfrom(
+                socket,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+                addr_ptr,
+                &mut sender_len,
+            ) {
+                d if d < 0 => println!("Error receiving: {}", Errorr::last_os_error()),
+                len => {
+                    println!(
+                        "Received {:?} bytes from {:x?}, packet type {}, protocol {:#06x}",
+                        len,
+                        &sender.sll_addr[..usize::from(sender.sll_halen).min(8)],
+                        sender.sll_pkttype,
+                        u16::from_be(sender.sll_protocol)
+                    );
+                    println!("Buffer content: {:x?}", &buf[..len as usize]);
+                }
+            }
         }
     }
 }
 
 fn main() {
+    let network_iface = match network_iface(std::env::args().skip(1)) {
+        Ok(iface) => iface,
+        Err(why) => {
+            eprintln!("{}\n{}", why, USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let listen_socket = unsafe {
         match libc::socket(libc::AF_PACKET, libc::SOCK_RAW, ETH_P_ARP.to_be() as i32) {
-            -1 => Err("Opening socket error"),
+            -1 => Err(SocketError {
+                action: "opening socket",
+                err: errno::errno(),
+            }),
             fd => Ok(fd),
         }
-    }
-    .unwrap();
+    };
+    let listen_socket = or_exit(listen_socket, &network_iface);
 
     println!("opening socket {:?}", listen_socket);
 
-    let ifindex = ifindex_from_ifname(NETWORK_IFACE, listen_socket).unwrap();
+    let ifindex = or_exit(
+        ifindex_from_ifname(&network_iface, listen_socket),
+        &network_iface,
+    );
+    or_exit(
+        check_iface_up(&network_iface, listen_socket),
+        &network_iface,
+    );
 
     println!("Interface address is {:?}", ifindex);
 
-    let if_hwaddr = ifhwaddr_from_ifname(NETWORK_IFACE, listen_socket).unwrap();
+    let if_hwaddr = or_exit(
+        ifhwaddr_from_ifname(&network_iface, listen_socket),
+        &network_iface,
+    );
     println!("IF HW addr is {:x?}", if_hwaddr);
 
     let listen_sockaddr = libc::sockaddr_ll {
@@ -286,7 +387,6 @@ fn main() {
         sll_addr: [0; 8],
     };
 
-
     match bind_to_iface(listen_socket, ifindex) {
         Err(why) => panic!("{:?}", why),
         _ => (),
@@ -297,10 +397,66 @@ fn main() {
     let mut buf: [u8; 1024] = [0; 1024];
     let mut len: usize = 0;
 
-
     //len = nix_recv(listen_socket, &mut buf, MsgFlags::empty()).unwrap();
 
     rcv_frame(listen_socket);
     //println!("Received {:?} bytes", len);
     //println!("Buffer content: {:x?}", buf);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn interface_arguments() {
+        for given in [
+            &["-i", "plc0"][..],
+            &["--iface", "plc0"],
+            &["--iface=plc0"],
+            &["-i", "eth1", "--iface=plc0"],
+        ] {
+            assert_eq!(
+                network_iface(args(given)),
+                Ok("plc0".to_string()),
+                "{:?}",
+                given
+            );
+        }
+        for given in [
+            &["-i"][..],
+            &["--iface"],
+            &["plc0"],
+            &["-x", "plc0"],
+            &["--iface", "plc0", "-v"],
+        ] {
+            assert!(network_iface(args(given)).is_err(), "{:?}", given);
+        }
+        // The only test touching the environment
+        std::env::set_var(NETWORK_IFACE_ENV, "plc1");
+        assert_eq!(network_iface(args(&[])), Ok("plc1".to_string()));
+        assert_eq!(network_iface(args(&["-i", "plc0"])), Ok("plc0".to_string()));
+        std::env::remove_var(NETWORK_IFACE_ENV);
+        assert_eq!(
+            network_iface(args(&[])),
+            Ok(DEFAULT_NETWORK_IFACE.to_string())
+        );
+    }
+
+    #[test]
+    fn interface_names() {
+        for name in ["eth1", "plc0", "enp0s31f6", "a23456789012345"] {
+            assert!(check_ifname(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".", "..", "eth0:1", "a/b", "eth 0", "a234567890123456"] {
+            assert!(check_ifname(name).is_err(), "{:?}", name);
+        }
+    }
+}
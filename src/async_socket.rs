@@ -0,0 +1,202 @@
+//! `RawSocket` for tokio
+//!
+//! The socket is switched to non-blocking and registered with the reactor,
+//! every call waits for readiness and then does what the blocking call does.
+//! A read is only attempted once the socket is readable and completes without
+//! yielding, so dropping a pending future never loses a frame.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::unix::AsyncFd;
+
+use crate::error::{Error, Result};
+use crate::mme::Mme;
+use crate::socket::{FrameInfo, RawSocket};
+
+// Largest untagged frame plus an 802.1Q tag
+const FRAME_BUFFER_LEN: usize = 1522;
+
+fn would_block(err: &Error) -> bool {
+    matches!(err, Error::Os { source, .. } if source.kind() == io::ErrorKind::WouldBlock)
+}
+
+fn reactor_error(source: io::Error) -> Error {
+    Error::Os {
+        action: "waiting for socket readiness",
+        source,
+    }
+}
+
+#[derive(Debug)]
+pub struct AsyncRawSocket {
+    inner: AsyncFd<RawSocket>,
+}
+
+impl AsyncRawSocket {
+    /// Has to be called from within a tokio runtime
+    pub fn new(socket: RawSocket) -> Result<Self> {
+        socket.set_nonblocking(true)?;
+        let inner = AsyncFd::new(socket).map_err(|source| Error::Os {
+            action: "registering socket",
+            source,
+        })?;
+        Ok(AsyncRawSocket { inner })
+    }
+
+    /// `RawSocket::open` for tokio
+    pub fn open(ifname: &str, ether_type: u16) -> Result<Self> {
+        AsyncRawSocket::new(RawSocket::open(ifname, ether_type)?)
+    }
+
+    pub fn get_ref(&self) -> &RawSocket {
+        self.inner.get_ref()
+    }
+
+    /// The socket is left non-blocking
+    pub fn into_inner(self) -> RawSocket {
+        self.inner.into_inner()
+    }
+
+    pub async fn send_frame(&self, frame: &[u8]) -> Result<usize> {
+        loop {
+            let mut guard = self.inner.writable().await.map_err(reactor_error)?;
+            match self.get_ref().send_frame(frame) {
+                Err(err) if would_block(&err) => guard.clear_ready(),
+                res => return res,
+            }
+        }
+    }
+
+    pub async fn recv_frame(&self, buffer: &mut [u8]) -> Result<usize> {
+        loop {
+            let mut guard = self.inner.readable().await.map_err(reactor_error)?;
+            match self.get_ref().recv_frame(buffer) {
+                Err(err) if would_block(&err) => guard.clear_ready(),
+                res => return res,
+            }
+        }
+    }
+
+    pub async fn recv_frame_from(&self, buffer: &mut [u8]) -> Result<FrameInfo> {
+        loop {
+            let mut guard = self.inner.readable().await.map_err(reactor_error)?;
+            match self.get_ref().recv_frame_from(buffer) {
+                Err(err) if would_block(&err) => guard.clear_ready(),
+                res => return res,
+            }
+        }
+    }
+
+    /// Received frames decoded as MMEs. Anything else on the socket comes
+    /// out as an error, which `Filter::homeplug_av` keeps from happening.
+    pub fn mmes(&self) -> Mmes<'_> {
+        Mmes {
+            socket: self,
+            buffer: [0; FRAME_BUFFER_LEN],
+        }
+    }
+}
+
+/// Stream of `AsyncRawSocket::mmes`, it never ends
+pub struct Mmes<'a> {
+    socket: &'a AsyncRawSocket,
+    buffer: [u8; FRAME_BUFFER_LEN],
+}
+
+impl<'a> Stream for Mmes<'a> {
+    type Item = Result<(Mme, FrameInfo)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = match this.socket.inner.poll_read_ready(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(source)) => return Poll::Ready(Some(Err(reactor_error(source)))),
+                Poll::Ready(Ok(guard)) => guard,
+            };
+            match this.socket.get_ref().recv_frame_from(&mut this.buffer) {
+                Err(err) if would_block(&err) => guard.clear_ready(),
+                Err(err) => return Poll::Ready(Some(Err(err))),
+                Ok(info) => {
+                    let frame = &this.buffer[..info.len.min(FRAME_BUFFER_LEN)];
+                    let mme = Mme::from_bytes(frame).map_err(Error::from);
+                    return Poll::Ready(Some(mme.map(|mme| (mme, info))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    // Nothing else sends it on lo, the socket tests use other ethertypes
+    const ETH_P_TEST: u16 = 0x88bd;
+
+    /// `None` without CAP_NET_RAW
+    fn loopback() -> Option<RawSocket> {
+        match RawSocket::open("lo", ETH_P_TEST) {
+            Ok(socket) => Some(socket),
+            Err(Error::Os { source, .. }) if source.kind() == io::ErrorKind::PermissionDenied => {
+                None
+            }
+            Err(err) => panic!("opening lo: {}", err),
+        }
+    }
+
+    fn frame(seq: u8) -> [u8; 60] {
+        let mut frame = [0; 60];
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x05]);
+        frame[12..14].copy_from_slice(&ETH_P_TEST.to_be_bytes());
+        frame[14] = seq;
+        frame
+    }
+
+    #[tokio::test]
+    async fn cancelled_receive_loses_nothing() {
+        let (receiver, sender) = match (loopback(), loopback()) {
+            (Some(receiver), Some(sender)) => (AsyncRawSocket::new(receiver).unwrap(), sender),
+            _ => return,
+        };
+        let mut buffer = [0; 64];
+        let idle = Duration::from_millis(20);
+        assert!(timeout(idle, receiver.recv_frame(&mut buffer))
+            .await
+            .is_err());
+
+        sender.send_frame(&frame(1)).unwrap();
+        let info = receiver.recv_frame_from(&mut buffer).await.unwrap();
+        assert_eq!((info.len, buffer[14]), (60, 1));
+    }
+
+    #[tokio::test]
+    async fn stale_readiness_waits_for_the_next_frame() {
+        let (receiver, sender) = match (loopback(), loopback()) {
+            (Some(receiver), Some(sender)) => (AsyncRawSocket::new(receiver).unwrap(), sender),
+            _ => return,
+        };
+        let mut buffer = [0; 64];
+        assert!(would_block(
+            &receiver.get_ref().recv_frame(&mut buffer).unwrap_err()
+        ));
+
+        // The reactor saw the frame, but it's gone by the time of the read
+        sender.send_frame(&frame(1)).unwrap();
+        drop(receiver.inner.readable().await.unwrap());
+        receiver.get_ref().recv_frame(&mut buffer).unwrap();
+        let idle = Duration::from_millis(20);
+        assert!(timeout(idle, receiver.recv_frame(&mut buffer))
+            .await
+            .is_err());
+
+        sender.send_frame(&frame(2)).unwrap();
+        assert_eq!(receiver.recv_frame(&mut buffer).await.unwrap(), 60);
+        assert_eq!(buffer[14], 2);
+    }
+}
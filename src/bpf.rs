@@ -0,0 +1,261 @@
+//! Classic BPF socket filters
+//!
+//! A filter runs in the kernel for every frame the socket would get, so on a
+//! busy link the SLAC code is only woken for the frames it cares about. The
+//! filters look at untagged Ethernet frames: an 802.1Q tag shifts the
+//! ethertype out of the place they check.
+
+use std::convert::TryFrom;
+
+use crate::error::{Error, Result};
+use crate::mme::ETH_P_HOMEPLUG_AV;
+
+const ETH_P_ARP: u16 = 0x0806;
+// Offsets into an untagged frame
+const ETHER_TYPE_OFFSET: u32 = 12;
+const MMTYPE_OFFSET: u32 = 15;
+// Longest program the kernel accepts, BPF_MAXINSNS
+const MAX_INSTRUCTIONS: usize = 4096;
+
+// Opcodes from linux/filter.h
+const BPF_LD: u16 = 0x00;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+const BPF_OR: u16 = 0x40;
+const BPF_LSH: u16 = 0x60;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_TAX: u16 = 0x00;
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    EtherType(u16),
+    MmTypeRange(u16, u16),
+}
+
+/// A program ready for `RawSocket::attach_filter`
+#[derive(Debug, Clone)]
+pub struct Filter {
+    program: Vec<libc::sock_filter>,
+}
+
+impl Filter {
+    pub fn builder() -> FilterBuilder {
+        FilterBuilder {
+            conditions: Vec::new(),
+            snaplen: u32::MAX,
+        }
+    }
+
+    /// HomePlug AV management messages, SLAC included
+    pub fn homeplug_av() -> Self {
+        Filter::builder()
+            .ether_type(ETH_P_HOMEPLUG_AV)
+            .build()
+            .expect("fixed filter")
+    }
+
+    pub fn arp() -> Self {
+        Filter::builder()
+            .ether_type(ETH_P_ARP)
+            .build()
+            .expect("fixed filter")
+    }
+
+    /// HomePlug AV management messages with an MMTYPE in `first..=last`
+    pub fn mme(first: u16, last: u16) -> Self {
+        Filter::builder()
+            .ether_type(ETH_P_HOMEPLUG_AV)
+            .mmtype_range(first, last)
+            .build()
+            .expect("fixed filter")
+    }
+
+    pub fn instructions(&self) -> &[libc::sock_filter] {
+        &self.program
+    }
+}
+
+/// Frames have to meet every condition added to be accepted
+#[derive(Debug, Clone)]
+pub struct FilterBuilder {
+    conditions: Vec<Condition>,
+    snaplen: u32,
+}
+
+impl FilterBuilder {
+    pub fn ether_type(mut self, ether_type: u16) -> Self {
+        self.conditions.push(Condition::EtherType(ether_type));
+        self
+    }
+
+    /// MMTYPE in `first..=last`, only meaningful along with the HomePlug AV
+    /// ethertype
+    pub fn mmtype_range(mut self, first: u16, last: u16) -> Self {
+        self.conditions.push(Condition::MmTypeRange(first, last));
+        self
+    }
+
+    /// Bytes of an accepted frame passed on, the whole frame by default
+    pub fn snaplen(mut self, snaplen: u32) -> Self {
+        self.snaplen = snaplen;
+        self
+    }
+
+    pub fn build(self) -> Result<Filter> {
+        // Every check jumps forward to the final reject on a mismatch, which
+        // is patched in once the length is known
+        let mut program = Vec::new();
+        let mut rejects = Vec::new();
+        for condition in &self.conditions {
+            match *condition {
+                Condition::EtherType(ether_type) => {
+                    program.push(stmt(BPF_LD | BPF_H | BPF_ABS, ETHER_TYPE_OFFSET));
+                    rejects.push((program.len(), false));
+                    program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, u32::from(ether_type), 0, 0));
+                }
+                Condition::MmTypeRange(first, last) => {
+                    if first > last {
+                        return Err(Error::InvalidFilter("empty MMTYPE range"));
+                    }
+                    // MMTYPE is little-endian, the loads are big-endian
+                    program.push(stmt(BPF_LD | BPF_B | BPF_ABS, MMTYPE_OFFSET + 1));
+                    program.push(stmt(BPF_ALU | BPF_LSH | BPF_K, 8));
+                    program.push(stmt(BPF_MISC | BPF_TAX, 0));
+                    program.push(stmt(BPF_LD | BPF_B | BPF_ABS, MMTYPE_OFFSET));
+                    program.push(stmt(BPF_ALU | BPF_OR | BPF_X, 0));
+                    rejects.push((program.len(), false));
+                    program.push(jump(BPF_JMP | BPF_JGE | BPF_K, u32::from(first), 0, 0));
+                    rejects.push((program.len(), true));
+                    program.push(jump(BPF_JMP | BPF_JGT | BPF_K, u32::from(last), 0, 0));
+                }
+            }
+        }
+        program.push(stmt(BPF_RET | BPF_K, self.snaplen));
+        let reject = program.len();
+        program.push(stmt(BPF_RET | BPF_K, 0));
+        if program.len() > MAX_INSTRUCTIONS {
+            return Err(Error::InvalidFilter("too many instructions"));
+        }
+        for (index, on_true) in rejects {
+            let offset = u8::try_from(reject - index - 1)
+                .map_err(|_| Error::InvalidFilter("jump out of range"))?;
+            if on_true {
+                program[index].jt = offset;
+            } else {
+                program[index].jf = offset;
+            }
+        }
+        Ok(Filter { program })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mme::{CM_SLAC_MATCH, CM_SLAC_PARM};
+
+    /// Runs the subset of classic BPF the builder emits
+    fn run(filter: &Filter, frame: &[u8]) -> u32 {
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0usize);
+        loop {
+            let insn = filter.instructions()[pc];
+            pc += 1;
+            let k = insn.k as usize;
+            match insn.code {
+                c if c == BPF_LD | BPF_H | BPF_ABS => match frame.get(k..k + 2) {
+                    Some(half) => a = u32::from(u16::from_be_bytes([half[0], half[1]])),
+                    None => return 0,
+                },
+                c if c == BPF_LD | BPF_B | BPF_ABS => match frame.get(k) {
+                    Some(byte) => a = u32::from(*byte),
+                    None => return 0,
+                },
+                c if c == BPF_ALU | BPF_LSH | BPF_K => a <<= insn.k,
+                c if c == BPF_ALU | BPF_OR | BPF_X => a |= x,
+                c if c == BPF_MISC | BPF_TAX => x = a,
+                c if c == BPF_RET | BPF_K => return insn.k,
+                c => {
+                    let taken = match c & 0xf0 {
+                        BPF_JEQ => a == insn.k,
+                        BPF_JGT => a > insn.k,
+                        BPF_JGE => a >= insn.k,
+                        _ => panic!("unexpected opcode {:#x}", c),
+                    };
+                    pc += usize::from(if taken { insn.jt } else { insn.jf });
+                }
+            }
+        }
+    }
+
+    fn frame(ether_type: u16, mmtype: u16) -> Vec<u8> {
+        let mut frame = vec![0; 60];
+        frame[12..14].copy_from_slice(&ether_type.to_be_bytes());
+        frame[14] = 1;
+        frame[15..17].copy_from_slice(&mmtype.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn ether_types() {
+        let homeplug = frame(ETH_P_HOMEPLUG_AV, CM_SLAC_PARM);
+        let arp = frame(ETH_P_ARP, 0);
+        let ipv6 = frame(0x86dd, 0);
+        assert_eq!(run(&Filter::homeplug_av(), &homeplug), u32::MAX);
+        assert_eq!(run(&Filter::homeplug_av(), &arp), 0);
+        assert_eq!(run(&Filter::homeplug_av(), &ipv6), 0);
+        assert_eq!(run(&Filter::arp(), &arp), u32::MAX);
+        assert_eq!(run(&Filter::arp(), &homeplug), 0);
+        assert_eq!(run(&Filter::arp(), &homeplug[..10]), 0);
+    }
+
+    #[test]
+    fn mmtype_range() {
+        let slac = Filter::mme(CM_SLAC_PARM, CM_SLAC_MATCH | 3);
+        for mmtype in [CM_SLAC_PARM, CM_SLAC_PARM | 1, CM_SLAC_MATCH | 3] {
+            assert_eq!(run(&slac, &frame(ETH_P_HOMEPLUG_AV, mmtype)), u32::MAX);
+        }
+        // 0x6064 byte swapped is 0x6460, which a big-endian load would let in
+        for mmtype in [CM_SLAC_PARM - 1, CM_SLAC_MATCH + 4, 0x6460, 0xa000] {
+            assert_eq!(run(&slac, &frame(ETH_P_HOMEPLUG_AV, mmtype)), 0);
+        }
+        assert_eq!(run(&slac, &frame(ETH_P_ARP, CM_SLAC_PARM)), 0);
+    }
+
+    #[test]
+    fn builder_limits() {
+        let filter = Filter::builder()
+            .ether_type(ETH_P_HOMEPLUG_AV)
+            .snaplen(64)
+            .build()
+            .unwrap();
+        assert_eq!(run(&filter, &frame(ETH_P_HOMEPLUG_AV, 0)), 64);
+        assert!(matches!(
+            Filter::builder().mmtype_range(2, 1).build(),
+            Err(Error::InvalidFilter(_))
+        ));
+        let long = (0..40).fold(Filter::builder(), |builder, _| builder.mmtype_range(0, 1));
+        assert!(matches!(long.build(), Err(Error::InvalidFilter(_))));
+    }
+}
@@ -0,0 +1,193 @@
+//! Wire codec for the MME payloads
+//!
+//! HomePlug AV lays its fields out back to back, multi-byte integers in
+//! little-endian. `Writer` and `Reader` walk a buffer field by field so every
+//! message spells out its exact layout.
+
+use std::error::Error;
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodeError {
+    /// The buffer holds `available` bytes while `needed` are written
+    BufferTooSmall { needed: usize, available: usize },
+    /// `field` has more entries than its count on the wire can tell
+    TooManyEntries { field: &'static str, count: usize },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::BufferTooSmall { needed, available } => {
+                write!(
+                    f,
+                    "buffer of {} bytes, {} needed to encode",
+                    available, needed
+                )
+            }
+            EncodeError::TooManyEntries { field, count } => {
+                write!(f, "{} entries of {} don't fit its count", count, field)
+            }
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The input ended at `offset` while `needed` more bytes were expected
+    Truncated { offset: usize, needed: usize },
+    /// The field starting at `offset` holds a value that isn't allowed
+    Invalid { field: &'static str, offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { offset, needed } => {
+                write!(
+                    f,
+                    "input truncated at offset {}, {} more bytes needed",
+                    offset, needed
+                )
+            }
+            DecodeError::Invalid { field, offset } => {
+                write!(f, "invalid {} at offset {}", field, offset)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+pub trait Encode {
+    /// Number of bytes `encode` writes
+    fn encoded_len(&self) -> usize;
+
+    /// Writes the wire representation at the start of `buffer`, returning the
+    /// number of bytes written
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError>;
+
+    fn to_vec(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut buffer = vec![0; self.encoded_len()];
+        self.encode(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+pub trait Decode: Sized {
+    /// Parses the wire representation at the start of `buffer`, anything
+    /// after it (e.g. Ethernet padding) is ignored
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError>;
+}
+
+pub struct Writer<'a> {
+    buffer: &'a mut [u8],
+    offset: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Checks up front that `len` bytes fit into `buffer`
+    pub fn new(buffer: &'a mut [u8], len: usize) -> Result<Self, EncodeError> {
+        if buffer.len() < len {
+            return Err(EncodeError::BufferTooSmall {
+                needed: len,
+                available: buffer.len(),
+            });
+        }
+        Ok(Writer {
+            buffer: &mut buffer[..len],
+            offset: 0,
+        })
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buffer[self.offset] = value;
+        self.offset += 1;
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        LittleEndian::write_u16(&mut self.buffer[self.offset..], value);
+        self.offset += 2;
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.buffer[self.offset..self.offset + value.len()].copy_from_slice(value);
+        self.offset += value.len();
+    }
+
+    /// Zero fills whatever is left and returns the number of bytes written
+    pub fn finish(self) -> usize {
+        self.buffer[self.offset..].fill(0);
+        self.buffer.len()
+    }
+}
+
+pub struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Reader { buffer, offset: 0 }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let remaining = self.buffer.len() - self.offset;
+        if remaining < len {
+            return Err(DecodeError::Truncated {
+                offset: self.buffer.len(),
+                needed: len - remaining,
+            });
+        }
+        let bytes = &self.buffer[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a byte that may only hold one of the `allowed` values
+    pub fn u8_in(&mut self, field: &'static str, allowed: &[u8]) -> Result<u8, DecodeError> {
+        let offset = self.offset;
+        let value = self.u8()?;
+        if !allowed.contains(&value) {
+            return Err(DecodeError::Invalid { field, offset });
+        }
+        Ok(value)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(LittleEndian::read_u16(self.take(2)?))
+    }
+
+    /// Reads a little-endian u16 that must hold `expected`
+    pub fn u16_eq(&mut self, field: &'static str, expected: u16) -> Result<u16, DecodeError> {
+        let offset = self.offset;
+        let value = self.u16()?;
+        if value != expected {
+            return Err(DecodeError::Invalid { field, offset });
+        }
+        Ok(value)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buffer[self.offset..]
+    }
+}
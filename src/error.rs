@@ -0,0 +1,152 @@
+//! Crate wide error type
+//!
+//! Every fallible call of the crate ends up in `Error`, so a service driving
+//! the sockets and state machines can log what went wrong and carry on.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::codec::{DecodeError, EncodeError};
+use crate::messages::SetKeyError;
+use crate::mme::MmeError;
+use crate::slac::SlacError;
+use crate::socket::IFNAMSIZ;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A system call failed while `action`
+    Os {
+        action: &'static str,
+        source: io::Error,
+    },
+    /// Not something the kernel accepts as an interface name
+    InvalidInterfaceName(String),
+    /// Nothing was received within the given time
+    Timeout(Duration),
+    NoSuchInterface(String),
+    /// The interface exists, but isn't administratively up
+    InterfaceDown(String),
+    /// The interface is up, but there's no link, e.g. the modem is unplugged
+    NoCarrier(String),
+    /// A socket filter the kernel would refuse
+    InvalidFilter(&'static str),
+    /// A receive ring layout the kernel would refuse
+    InvalidRingConfig(&'static str),
+    Encode(EncodeError),
+    /// A payload couldn't be parsed, at the given offset
+    Decode(DecodeError),
+    /// A received frame couldn't be parsed, payload errors carry the offset
+    Frame(MmeError),
+    /// The peer didn't follow the SLAC protocol
+    Slac(SlacError),
+    SetKey(SetKeyError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn os(action: &'static str) -> Self {
+        Error::Os {
+            action,
+            source: io::Error::last_os_error(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Os { action, source } => write!(f, "{}: {}", action, source),
+            Error::InvalidInterfaceName(name) => write!(
+                f,
+                "invalid interface name {:?}, at most {} characters without '/', ':' or spaces",
+                name,
+                IFNAMSIZ - 1
+            ),
+            Error::Timeout(timeout) => write!(f, "nothing received within {:?}", timeout),
+            Error::NoSuchInterface(name) => write!(f, "no interface named {:?}", name),
+            Error::InterfaceDown(name) => write!(f, "interface {:?} is down", name),
+            Error::NoCarrier(name) => write!(f, "interface {:?} has no carrier", name),
+            Error::InvalidFilter(why) => write!(f, "invalid socket filter: {}", why),
+            Error::InvalidRingConfig(why) => write!(f, "invalid receive ring: {}", why),
+            Error::Encode(err) => write!(f, "encoding failed: {}", err),
+            Error::Decode(err) => write!(f, "decoding failed: {}", err),
+            Error::Frame(err) => write!(f, "malformed frame: {}", err),
+            Error::Slac(err) => write!(f, "SLAC: {}", err),
+            Error::SetKey(err) => write!(f, "CM_SET_KEY.REQ: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Os { source, .. } => Some(source),
+            Error::InvalidInterfaceName(_)
+            | Error::Timeout(_)
+            | Error::NoSuchInterface(_)
+            | Error::InterfaceDown(_)
+            | Error::NoCarrier(_)
+            | Error::InvalidFilter(_)
+            | Error::InvalidRingConfig(_) => None,
+            Error::Encode(err) => Some(err),
+            Error::Decode(err) => Some(err),
+            Error::Frame(err) => Some(err),
+            Error::Slac(err) => Some(err),
+            Error::SetKey(err) => Some(err),
+        }
+    }
+}
+
+impl From<EncodeError> for Error {
+    fn from(err: EncodeError) -> Self {
+        Error::Encode(err)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Self {
+        Error::Decode(err)
+    }
+}
+
+impl From<MmeError> for Error {
+    fn from(err: MmeError) -> Self {
+        Error::Frame(err)
+    }
+}
+
+impl From<SlacError> for Error {
+    fn from(err: SlacError) -> Self {
+        Error::Slac(err)
+    }
+}
+
+impl From<SetKeyError> for Error {
+    fn from(err: SetKeyError) -> Self {
+        Error::SetKey(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn wrapped_errors_are_the_source() {
+        let decode = DecodeError::Invalid {
+            field: "NumGroups",
+            offset: 6,
+        };
+        let err = Error::from(decode.clone());
+        assert_eq!(
+            err.to_string(),
+            "decoding failed: invalid NumGroups at offset 6"
+        );
+        assert_eq!(err.source().unwrap().to_string(), decode.to_string());
+        assert!(Error::Timeout(Duration::from_secs(1)).source().is_none());
+    }
+}
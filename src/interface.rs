@@ -0,0 +1,183 @@
+//! Network interface introspection
+//!
+//! SIOCGIFADDR only knows about the primary IPv4 address, so the addresses,
+//! the hardware address and the flags all come from getifaddrs(3). Only the
+//! MTU needs an ioctl.
+
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+
+use crate::error::{Error, Result};
+use crate::messages::MacAddr;
+use crate::socket::{ifr_name, validate_ifname, IFNAMSIZ};
+
+const SIOCGIFMTU: libc::Ioctl = 0x8921;
+// Not exported by libc for every target, from linux/if.h
+const IFF_LOWER_UP: u32 = 0x10000;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct ifreq_ifmtu {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_mtu: libc::c_int,
+    _pad: [u8; 20],
+}
+
+/// `IFF_*` flags of an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceFlags(pub u32);
+
+impl InterfaceFlags {
+    /// Administratively up
+    pub fn is_up(self) -> bool {
+        self.0 & libc::IFF_UP as u32 != 0
+    }
+
+    /// Operationally up
+    pub fn is_running(self) -> bool {
+        self.0 & libc::IFF_RUNNING as u32 != 0
+    }
+
+    pub fn is_promiscuous(self) -> bool {
+        self.0 & libc::IFF_PROMISC as u32 != 0
+    }
+
+    /// The driver sees a carrier, for a PLC modem that it's connected
+    pub fn has_carrier(self) -> bool {
+        self.0 & IFF_LOWER_UP != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub hw_addr: MacAddr,
+    pub mtu: u32,
+    pub flags: InterfaceFlags,
+    /// Every IPv4 and IPv6 address assigned to the interface
+    pub addresses: Vec<IpAddr>,
+}
+
+impl Interface {
+    pub fn query(name: &str) -> Result<Self> {
+        validate_ifname(name)?;
+        let mut interface = Interface {
+            name: name.to_string(),
+            index: 0,
+            hw_addr: [0; 6],
+            mtu: 0,
+            flags: InterfaceFlags(0),
+            addresses: Vec::new(),
+        };
+        let mut found = false;
+        let mut ifaddrs = ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut ifaddrs) } == -1 {
+            return Err(Error::os("listing interface addresses"));
+        }
+        let mut ifa = ifaddrs;
+        while let Some(entry) = unsafe { ifa.as_ref() } {
+            ifa = entry.ifa_next;
+            let entry_name = unsafe { CStr::from_ptr(entry.ifa_name) };
+            if entry_name.to_bytes() != name.as_bytes() {
+                continue;
+            }
+            found = true;
+            interface.flags = InterfaceFlags(entry.ifa_flags);
+            if entry.ifa_addr.is_null() {
+                continue;
+            }
+            unsafe { interface.add_address(entry.ifa_addr) };
+        }
+        unsafe { libc::freeifaddrs(ifaddrs) };
+        if !found {
+            return Err(Error::NoSuchInterface(name.to_string()));
+        }
+        interface.mtu = mtu_from_ifname(name)?;
+        Ok(interface)
+    }
+
+    /// Picks up what `addr` tells about the interface, it has to point to a
+    /// sockaddr of the size its family implies
+    unsafe fn add_address(&mut self, addr: *const libc::sockaddr) {
+        match libc::c_int::from((*addr).sa_family) {
+            libc::AF_INET => {
+                let sin = &*(addr as *const libc::sockaddr_in);
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                self.addresses.push(IpAddr::V4(ip));
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(addr as *const libc::sockaddr_in6);
+                self.addresses
+                    .push(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)));
+            }
+            libc::AF_PACKET => {
+                let sll = &*(addr as *const libc::sockaddr_ll);
+                self.index = sll.sll_ifindex as u32;
+                self.hw_addr.copy_from_slice(&sll.sll_addr[..6]);
+            }
+            _ => (),
+        }
+    }
+
+    /// The fe80::/10 address SDP and the rest of ISO 15118-2 run on
+    pub fn ipv6_link_local(&self) -> Option<Ipv6Addr> {
+        self.addresses.iter().find_map(|address| match address {
+            IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => Some(*ip),
+            _ => None,
+        })
+    }
+
+    /// Fails unless the interface is up and has a carrier
+    pub fn check_link(&self) -> Result<()> {
+        if !self.flags.is_up() {
+            return Err(Error::InterfaceDown(self.name.clone()));
+        }
+        if !self.flags.has_carrier() {
+            return Err(Error::NoCarrier(self.name.clone()));
+        }
+        Ok(())
+    }
+}
+
+fn mtu_from_ifname(ifname: &str) -> Result<u32> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd == -1 {
+        return Err(Error::os("opening socket"));
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut ifr = ifreq_ifmtu {
+        ifr_name: ifr_name(ifname),
+        ifr_mtu: 0,
+        _pad: [0; 20],
+    };
+    if unsafe { libc::ioctl(sock.as_raw_fd(), SIOCGIFMTU, &mut ifr) } == -1 {
+        return Err(Error::os("getting MTU"));
+    }
+    Ok(ifr.ifr_mtu as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback() {
+        let lo = Interface::query("lo").unwrap();
+        assert!(lo.flags.is_up());
+        assert!(lo.index > 0);
+        assert!(lo.mtu > 0);
+        assert!(lo.addresses.contains(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(lo.ipv6_link_local(), None);
+    }
+
+    #[test]
+    fn missing_interface() {
+        match Interface::query("nosuchif0") {
+            Err(Error::NoSuchInterface(name)) => assert_eq!(name, "nosuchif0"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
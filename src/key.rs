@@ -0,0 +1,77 @@
+//! Network membership key (NMK) and network identifier (NID)
+//!
+//! The EVSE hands a fresh NMK to every PEV it matches with. The NID that goes
+//! along with it isn't random: HomePlug AV derives it from the NMK, so both
+//! always have to be taken from here together.
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Security level, carried in bits 4-5 of the last NID octet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLevel {
+    /// Simple Connect, the level ISO 15118-3 uses
+    SimpleConnect = 0,
+    Secure = 1,
+}
+
+/// A random NMK, drawn from the operating system RNG
+pub fn generate_nmk() -> [u8; 16] {
+    let mut nmk = [0; 16];
+    OsRng.fill_bytes(&mut nmk);
+    nmk
+}
+
+/// NID of the network keyed with `nmk`: SHA-256 of the NMK, hashed over four
+/// more times, truncated to 52 bits and followed by the security level
+pub fn derive_nid(nmk: &[u8; 16], level: SecurityLevel) -> [u8; 7] {
+    let mut digest = Sha256::digest(nmk);
+    for _ in 0..4 {
+        digest = Sha256::digest(digest);
+    }
+    let mut nid = [0; 7];
+    nid.copy_from_slice(&digest[..7]);
+    nid[6] = (nid[6] >> 4) | ((level as u8) << 4);
+    nid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // NMK of the default "HomePlugAV" password, as shipped by open-plc-utils
+    const NMK_HOMEPLUGAV: [u8; 16] = hex!("50 d3 e4 93 3f 85 5b 70 40 78 4d f8 15 aa 8d b7");
+    // NMK of the "HomePlugAV0123" password
+    const NMK_HOMEPLUGAV0123: [u8; 16] = hex!("b5 93 19 d7 e8 15 7b a0 01 b0 18 66 9c ce e3 0d");
+
+    #[test]
+    fn nid_of_default_nmk() {
+        assert_eq!(
+            derive_nid(&NMK_HOMEPLUGAV, SecurityLevel::SimpleConnect),
+            hex!("b0 f2 e6 95 66 6b 03")
+        );
+    }
+
+    #[test]
+    fn nid_of_other_nmk() {
+        assert_eq!(
+            derive_nid(&NMK_HOMEPLUGAV0123, SecurityLevel::SimpleConnect),
+            hex!("02 6b cb a5 35 4e 08")
+        );
+    }
+
+    #[test]
+    fn nid_carries_security_level() {
+        assert_eq!(
+            derive_nid(&NMK_HOMEPLUGAV, SecurityLevel::Secure),
+            hex!("b0 f2 e6 95 66 6b 13")
+        );
+    }
+
+    #[test]
+    fn generated_nmks_differ() {
+        assert_ne!(generate_nmk(), generate_nmk());
+    }
+}
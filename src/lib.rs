@@ -0,0 +1,20 @@
+//! HomePlug Green PHY / ISO 15118-3 SLAC over AF_PACKET raw sockets
+//!
+//! `socket` moves raw Ethernet frames, `mme` and `messages` turn them into
+//! typed management messages and `slac` runs the matching on top of them.
+
+#[cfg(feature = "tokio")]
+pub mod async_socket;
+pub mod bpf;
+pub mod codec;
+pub mod error;
+pub mod interface;
+pub mod key;
+pub mod messages;
+pub mod mme;
+pub mod netlink;
+pub mod ring;
+pub mod slac;
+pub mod socket;
+
+pub use error::{Error, Result};
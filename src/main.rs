@@ -1,52 +1,33 @@
-extern crate byteorder;
-extern crate errno;
-extern crate libc;
-
-use std::io::ErrorKind;
-use std::io::{self, Error as Errorr};
-
-use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
-use std::{convert::TryFrom, convert::TryInto, io::Write};
-
-use bincode;
-use hex_literal::hex;
-use serde::{Deserialize, Serialize};
-
-use std::mem;
-use std::{error::Error, net::TcpListener, os::unix::io::AsRawFd};
-
+use std::env;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::thread;
 use std::time::Duration;
 
-use nix::sys::{socket::recv, socket::send, socket::MsgFlags};
+use hex_literal::hex;
 
-const NETWORK_IFACE: &str = "eth0";
+use slac::bpf::Filter;
+use slac::codec::{Decode, Encode, EncodeError, Writer};
+use slac::interface::Interface;
+use slac::key::{self, SecurityLevel};
+use slac::messages::{MacAddr, Message, SetKeyReq};
+use slac::mme::Mme;
+use slac::netlink::{LinkEvent, LinkMonitor};
+use slac::socket::{RawSocket, ETH_P_ALL};
+use slac::Error;
 
-const CM_SET_KEY_TYPE: u8 = b'\x01';
-//According to 15118-3 this value should be 0x00 and not 0xAA
-const CM_SET_KEY_MY_NONCE: [u8; 4] = hex!("aa aa aa aa");
-const CM_SET_KEY_YOUR_NONCE: [u8; 4] = hex!("00 00 00 00");
-const CM_SET_KEY_PID: u8 = b'\x04';
-const CM_SET_KEY_PRN: [u8; 2] = hex!("00 00");
-const CM_SET_KEY_PMN: u8 = b'\x00';
-const CM_SET_KEY_NEW_EKS: u8 = b'\x01';
-const CM_SET_CCO_CAPAB: u8 = b'\x00';
+// Used when neither --iface nor SLAC_IFACE name the PLC interface
+const DEFAULT_NETWORK_IFACE: &str = "eth0";
+const NETWORK_IFACE_ENV: &str = "SLAC_IFACE";
 
-const ETH_P_ALL: u16 = 0x0003;
-const ETH_P_ARP: u16 = 0x0806; // from if_ether.h for SOCK_RAW
-const SIOCGIFADDR: ioctl_request_t = 0x8915;
-const SIOCGIFINDEX: ioctl_request_t = 0x8933;
-const IFNAMSIZ: usize = 16; // net/if.h
-const SIOCGIFHWADDR: ioctl_request_t = 0x8927;
+const USAGE: &str = "usage: slac [-i|--iface <interface>]";
 
-#[allow(non_camel_case_types)]
-type ioctl_request_t = libc::c_ulong;
+const ETH_P_ARP: u16 = 0x0806; // from if_ether.h for SOCK_RAW
 
-type MacAddr = [u8; 6];
+// How long the demo waits for a reply before moving on
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
 
 // An ARP packet with ethernet headers still attached
-#[repr(C)]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 struct RawArpFrame {
     // Ethernet frame headers
     destination_mac: MacAddr,
@@ -64,313 +45,292 @@ struct RawArpFrame {
     target_proto_addr: [u8; 4],
 }
 
-//#[repr(C, packed)] // This tells the compiler to represent the struct in memory exactly
-// with the order below, instead of shuffling things around for efficiency
-#[repr(C)]
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SetKeyReq {
-    key_type: u8,
-    my_nonce: [u8; 4],
-    your_nonce: [u8; 4],
-    pid: u8,
-    prn: [u8; 2],
-    pmn: u8,
-    cco_cap: u8,
-    nid: [u8; 7],
-    new_eks: u8,
-    new_key: [u8; 16],
-}
-
-impl SetKeyReq {
-    pub fn new(nid: [u8; 7], new_key: [u8; 16]) -> Self {
-        SetKeyReq {
-            key_type: CM_SET_KEY_TYPE,
-            my_nonce: CM_SET_KEY_MY_NONCE,
-            your_nonce: CM_SET_KEY_YOUR_NONCE,
-            pid: CM_SET_KEY_PID,
-            prn: CM_SET_KEY_PRN,
-            pmn: CM_SET_KEY_PMN,
-            cco_cap: CM_SET_CCO_CAPAB,
-            nid,
-            new_eks: CM_SET_KEY_NEW_EKS,
-            new_key,
-        }
-    }
-    fn write_to_buffer<T: ByteOrder>(self, buffer: &mut [u8; 44]) {
-        let mut buffer = &mut buffer[..];
-        buffer.write(&self.nid);
-        buffer.write(&self.new_key);
-    }
-
-    pub fn to_bytes(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+// Unlike the MMEs every field goes out in network byte order, padded to the
+// minimum Ethernet frame length
+impl Encode for RawArpFrame {
+    fn encoded_len(&self) -> usize {
+        60
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        bincode::deserialize(bytes).unwrap()
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.bytes(&self.destination_mac);
+        w.bytes(&self.source_mac);
+        w.bytes(&self.ether_type.to_be_bytes());
+        w.bytes(&self.hardware_type.to_be_bytes());
+        w.bytes(&self.protocol_type.to_be_bytes());
+        w.u8(self.hw_addr_len);
+        w.u8(self.proto_addr_len);
+        w.bytes(&self.operation.to_be_bytes());
+        w.bytes(&self.sender_hw_addr);
+        w.bytes(&self.sender_proto_addr);
+        w.bytes(&self.target_hw_addr);
+        w.bytes(&self.target_proto_addr);
+        Ok(w.finish())
     }
 }
 
-#[derive(Debug)]
-struct SocketError {
-    action: &'static str,
-    err: errno::Errno,
+fn pollfd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
 }
 
-macro_rules! sockerr {
-    ($action:expr, $res:expr) => {
-        if $res == -1 {
-            return Err(SocketError {
-                action: $action,
-                err: errno::errno(),
-            });
+// Reopens the socket whenever the interface comes back with a new ifindex
+fn rcv_frame(iface: String, mut socket: Option<RawSocket>, mut monitor: LinkMonitor) {
+    loop {
+        let mut fds = vec![pollfd(monitor.as_raw_fd())];
+        fds.extend(socket.iter().map(|socket| pollfd(socket.as_raw_fd())));
+        println!("Start waiting for a frame...");
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+            println!("Error polling: {}", std::io::Error::last_os_error());
+            continue;
+        }
+        if fds[0].revents != 0 {
+            let events = match monitor.recv() {
+                Ok(events) => events,
+                Err(why) => {
+                    println!("Error receiving link events: {}", why);
+                    continue;
+                }
+            };
+            for event in events {
+                println!("Link event on {}: {:?}", iface, event);
+                match event {
+                    LinkEvent::Added { .. } => socket = reopen_mme_socket(&iface),
+                    // Opening fails on an interface added while down, it gets
+                    // another go once the interface is up
+                    LinkEvent::Carrier { up: true, .. } if socket.is_none() => {
+                        socket = reopen_mme_socket(&iface)
+                    }
+                    LinkEvent::Removed { .. } => socket = None,
+                    LinkEvent::Carrier { .. } => {}
+                }
+            }
+            continue;
+        }
+        if let Some(socket) = &socket {
+            let mut buf: [u8; 1024] = [0; 1024];
+            match socket.recv_frame_from(&mut buf) {
+                Err(why) => println!("Error receiving: {}", why),
+                Ok(info) => {
+                    println!(
+                        "Received {:?} bytes from {:x?} ({:?}) at {:?}",
+                        info.len, info.source, info.packet_type, info.timestamps.software
+                    );
+                    println!("Buffer content: {:x?}", &buf[..info.len.min(buf.len())]);
+                }
+            }
         }
-    };
-}
-
-#[repr(C)]
-#[allow(non_camel_case_types)]
-struct ifreq_ifindex {
-    ifr_name: [u8; IFNAMSIZ],
-    ifr_ifindex: libc::c_int,
-}
-
-#[repr(C)]
-#[allow(non_camel_case_types)]
-struct ifreq_ifhwaddr {
-    ifr_name: [u8; IFNAMSIZ],
-    ifr_ifhwaddr: libc::sockaddr,
-}
-
-fn ifhwaddr_from_ifname(ifname: &str, sock: libc::c_int) -> Result<MacAddr, SocketError> {
-    let mut ifr = ifreq_ifhwaddr {
-        ifr_name: [0; IFNAMSIZ],
-        ifr_ifhwaddr: unsafe { std::mem::zeroed() },
-    };
-    ifr.ifr_name.as_mut().write(ifname.as_bytes()).unwrap();
-    let res = unsafe { libc::ioctl(sock, SIOCGIFHWADDR.try_into().unwrap(), &ifr) };
-    sockerr!("getting ifhwaddr", res);
-    unsafe {
-        let slice_converted: &[u8] = std::mem::transmute(&ifr.ifr_ifhwaddr.sa_data[0..6]);
-        let hw_addr: MacAddr = slice_converted.try_into().unwrap();
-        return Ok(hw_addr);
     }
 }
 
-fn ifindex_from_ifname(ifname: &str, sock: libc::c_int) -> Result<libc::c_int, SocketError> {
-    let mut ifr = ifreq_ifindex {
-        ifr_name: [0; IFNAMSIZ],
-        ifr_ifindex: 0,
-    };
-    ifr.ifr_name.as_mut().write(ifname.as_bytes()).unwrap();
-    let res = unsafe { libc::ioctl(sock, SIOCGIFINDEX.try_into().unwrap(), &ifr) };
-    sockerr!("getting ifindex", res);
-    return Ok(ifr.ifr_ifindex);
-}
-
-pub fn socket_send_frame(socket: i32, frame: &[u8], ifindex: i32) -> io::Result<()> {
-    let sa = libc::sockaddr_ll {
-        sll_family: libc::AF_PACKET as u16,
-        sll_protocol: ETH_P_ARP.to_be(),
-        sll_ifindex: ifindex,
-        sll_hatype: 0,
-        sll_pkttype: 0,
-        sll_halen: 0,
-        sll_addr: [0; 8],
-    };
-
-    match unsafe {
-        let sa_ptr = &sa as *const libc::sockaddr_ll as *const libc::sockaddr;
-        libc::sendto(
-            socket,
-            frame.as_ptr() as *const libc::c_void,
-            frame.len(),
-            0,
-            sa_ptr,
-            mem::size_of_val(&sa) as libc::socklen_t,
-        )
-    } {
-        -1 => Err(Errorr::last_os_error()),
-        _ => Ok(()),
-    }
+// Only HomePlug AV frames wake the receive thread, not the IP traffic on the link
+fn open_mme_socket(iface: &str) -> slac::Result<RawSocket> {
+    let socket = RawSocket::open_with_filter(iface, ETH_P_ALL, &Filter::homeplug_av())?;
+    socket.enable_timestamps()?;
+    Ok(socket)
 }
 
-pub fn bind_to_iface(socket: i32, ifindex: i32) -> Result<String, String> {
-    let sockaddr = libc::sockaddr_ll {
-        sll_family: libc::AF_PACKET as u16,
-        sll_protocol: ETH_P_ALL.to_be(),
-        sll_ifindex: ifindex,
-        sll_hatype: 0,
-        sll_pkttype: 0,
-        sll_halen: 0,
-        sll_addr: [0; 8],
-    };
-
-    let addr_ptr = &sockaddr as *const libc::sockaddr_ll as *const libc::sockaddr;
-    match unsafe {
-        libc::bind(
-            socket,
-            addr_ptr,
-            std::mem::size_of_val(&sockaddr) as libc::socklen_t,
-        )
-    } {
-        -1 => Err("Bind to socket failed".to_string()),
-        _ => Ok("Error on socket bind".to_string()),
+fn reopen_mme_socket(iface: &str) -> Option<RawSocket> {
+    match open_mme_socket(iface) {
+        Ok(socket) => Some(socket),
+        Err(why) => {
+            println!("Error reopening socket: {}", why);
+            None
+        }
     }
 }
 
-pub fn rcv_frame(socket: i32) {
-    unsafe {
-        let socket =
-            match libc::socket(libc::AF_PACKET, libc::SOCK_RAW, ETH_P_ALL.to_be() as i32) {
-                -1 => Err("Opening socket error"),
-                fd => Ok(fd),
-            }
-            .unwrap();
-
-        let ifindex = ifindex_from_ifname(NETWORK_IFACE, socket).unwrap();
-
-        match bind_to_iface(socket, ifindex) {
-            Err(why) => panic!("{:?}", why),
-            _ => (),
-        }
-        loop {
-            let mut buf: [u8; 1024] = [0; 1024];
-            let mut len: usize;
-            println!("Start waiting for a frame...");
-            match libc::recv(socket, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) {
-                d if d < 0 => {
-                    println!("Error receiving");
-                    d as usize
-                }
-                len => {
-                    println!("Received {:?} bytes", len);
-                    println!("Buffer content: {:x?}", &buf[..len as usize]);
-                    len as usize
-                }
-            };
+/// Interface from `-i`/`--iface`, then from $SLAC_IFACE, then the default
+fn network_iface(mut args: impl Iterator<Item = String>) -> Result<String, String> {
+    let mut iface = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--iface" => match args.next() {
+                Some(name) => iface = Some(name),
+                None => return Err(format!("{} needs an interface name", arg)),
+            },
+            _ => match arg.strip_prefix("--iface=") {
+                Some(name) => iface = Some(name.to_string()),
+                None => return Err(format!("unexpected argument {:?}", arg)),
+            },
         }
     }
+    Ok(iface
+        .or_else(|| env::var(NETWORK_IFACE_ENV).ok())
+        .unwrap_or_else(|| DEFAULT_NETWORK_IFACE.to_string()))
 }
 
-fn main() {
-    let set_key_req = SetKeyReq::new([0x33, 0xaa, 0xaa, 0x11, 0xdd, 0xbb, 0x00], [0x00; 16]);
-    println!("SetKeyReq nid {:#04x?}", set_key_req.nid);
-    let mut set_key_ser = set_key_req.to_bytes();
-    println!("SetKeyReq nid {:x?}", set_key_req.nid);
-    println!("SetKeyReq nmk: {:x?}", set_key_req.new_key);
+fn run(iface: &str) -> slac::Result<()> {
+    let nmk = key::generate_nmk();
+    let nid = key::derive_nid(&nmk, SecurityLevel::SimpleConnect);
+    let set_key_req = SetKeyReq::new(nid, nmk);
+    println!("SetKeyReq nid {:#04x?}", set_key_req.nid());
+    let set_key_ser = set_key_req.to_vec()?;
+    println!("SetKeyReq nid {:x?}", set_key_req.nid());
+    println!("SetKeyReq nmk: {:x?}", set_key_req.new_key());
     println!(
         "struct SetKeyReq serializes into byte array {:x?}",
         set_key_ser
     );
-    let set_key_req_des: SetKeyReq = SetKeyReq::from_bytes(&set_key_ser);
+    let set_key_req_des = SetKeyReq::decode(&set_key_ser)?;
     println!("SetKeyReq Deserialized: {:x?}", set_key_req_des);
+    let set_key_frame = Mme::new(
+        hex!("00 b0 52 00 00 01"),
+        hex!("02 42 ac 18 00 02"),
+        Message::SetKeyReq(set_key_req),
+    );
+    println!("CM_SET_KEY.REQ frame: {:x?}", set_key_frame.to_vec()?);
 
-    let listen_socket = unsafe {
-        match libc::socket(libc::AF_PACKET, libc::SOCK_RAW, ETH_P_ARP.to_be() as i32) {
-            -1 => Err("Opening socket error"),
-            fd => Ok(fd),
-        }
-    }
-    .unwrap();
+    let interface = Interface::query(iface)?;
+    interface.check_link()?;
+    println!(
+        "{} mtu {} flags {:#x} addresses {:?}",
+        interface.name, interface.mtu, interface.flags.0, interface.addresses
+    );
+    println!("IPv6 link-local for SDP: {:?}", interface.ipv6_link_local());
+
+    let listen_socket = RawSocket::open(iface, ETH_P_ARP)?;
 
     println!("opening socket {:?}", listen_socket);
 
-    let ifindex = ifindex_from_ifname(NETWORK_IFACE, listen_socket).unwrap();
+    let ifindex = listen_socket.ifindex()?;
 
     println!("Interface address is {:?}", ifindex);
 
-    let if_hwaddr = ifhwaddr_from_ifname(NETWORK_IFACE, listen_socket).unwrap();
+    let if_hwaddr = listen_socket.hw_addr()?;
     println!("IF HW addr is {:x?}", if_hwaddr);
 
-    let listen_sockaddr = libc::sockaddr_ll {
-        sll_family: libc::AF_PACKET as u16,
-        sll_protocol: ETH_P_ARP.to_be(),
-        sll_ifindex: ifindex,
-        sll_hatype: 0,
-        sll_pkttype: 0,
-        sll_halen: 0,
-        sll_addr: [0; 8],
-    };
-
     let destination_mac: MacAddr = if_hwaddr;
 
     let frame_to_send = RawArpFrame {
         destination_mac,
         source_mac: hex!("02 42 ac 18 00 02"),
-        ether_type: ETH_P_ARP.to_be(),
+        ether_type: ETH_P_ARP,
         hardware_type: 0x0001u16,
         protocol_type: 0x0800u16,
         hw_addr_len: 6u8,
         proto_addr_len: 4u8,
-        operation: 1u16.to_be(),
+        operation: 1u16,
         sender_hw_addr: hex!("02 42 ac 18 00 02"),
         sender_proto_addr: hex!("7f 00 00 01 "),
         target_hw_addr: hex!("02 42 ac 18 00 02"),
         target_proto_addr: hex!("7f 00 00 01"),
     };
 
-    let mut frame_to_send_ser = bincode::serialize(&frame_to_send).unwrap();
-
-    let num_padding_bytes = 60 - frame_to_send_ser.len();
-    let mut vec_padd = vec![0u8; num_padding_bytes];
-    frame_to_send_ser.append(&mut vec_padd);
+    let frame_to_send_ser = frame_to_send.to_vec()?;
     println!("Frame to send: {:x?}", &frame_to_send_ser);
     println!("Frame length: {:?}", frame_to_send_ser.len());
 
-    //let result = socket_send_frame(listen_socket, &frame_to_send_ser, ifindex).unwrap();
-    //println!("Result: {:?}", result);
-    unsafe {
-        let addr_ptr = &listen_sockaddr as *const libc::sockaddr_ll as *const libc::sockaddr;
-        match libc::sendto(
-            listen_socket,
-            frame_to_send_ser.as_ptr() as *const libc::c_void,
-            frame_to_send_ser.len(),
-            0,
-            addr_ptr,
-            mem::size_of_val(&listen_sockaddr) as libc::socklen_t,
-        ) {
-            d if d < 0 => println!("Error sending reply"),
-            _ => println!("Sent an ARP reply"),
-        }
-    }
-
-    match bind_to_iface(listen_socket, ifindex) {
-        Err(why) => panic!("{:?}", why),
-        _ => (),
+    match listen_socket.send_frame(&frame_to_send_ser) {
+        Err(why) => println!("Error sending reply: {}", why),
+        Ok(_) => println!("Sent an ARP reply"),
     }
 
-    thread::spawn(move || rcv_frame(listen_socket));
+    let monitor = LinkMonitor::open(iface)?;
+    let mme_socket = open_mme_socket(iface)?;
+    let rcv_iface = iface.to_string();
+    thread::spawn(move || rcv_frame(rcv_iface, Some(mme_socket), monitor));
     thread::sleep(Duration::from_millis(10));
 
-    unsafe {
-        match libc::send(
-            listen_socket,
-            frame_to_send_ser.as_ptr() as *const libc::c_void,
-            frame_to_send_ser.len(),
-            0,
-        ) {
-            d if d < 0 => println!("Error sending a raw packet!"),
-            _ => println!("Successfully sent a Packet"),
-        }
+    match listen_socket.send_frame(&frame_to_send_ser) {
+        Err(why) => println!("Error sending a raw packet: {}", why),
+        Ok(_) => println!("Successfully sent a Packet"),
     }
     let mut buf: [u8; 1024] = [0; 1024];
-    let mut len: usize = 0;
-
-    len = recv(listen_socket, &mut buf, MsgFlags::empty()).unwrap();
-    println!("Received {:?} bytes", len);
-
-    unsafe {
-        match libc::write(
-            listen_socket,
-            frame_to_send_ser.as_ptr() as *const libc::c_void,
-            frame_to_send_ser.len(),
-        ) {
-            -1 => println!("Send didnt work!!"),
-            _ => println!("Success again"),
-        }
+    match listen_socket.recv_timeout(&mut buf, RECV_TIMEOUT) {
+        Ok(len) => println!("Received {:?} bytes", len),
+        Err(Error::Timeout(timeout)) => println!("No frame within {:?}", timeout),
+        Err(why) => return Err(why),
     }
 
     println!("Final send");
-    send(listen_socket, &frame_to_send_ser, MsgFlags::empty()).unwrap();
+    listen_socket.send_frame(&frame_to_send_ser)?;
+    Ok(())
+}
+
+fn main() {
+    let iface = match network_iface(env::args().skip(1)) {
+        Ok(iface) => iface,
+        Err(why) => {
+            eprintln!("{}\n{}", why, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(why) = run(&iface) {
+        eprintln!("{}", why);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_frame_fields_are_big_endian() {
+        let frame = RawArpFrame {
+            destination_mac: [0xff; 6],
+            source_mac: hex!("02 42 ac 18 00 02"),
+            ether_type: ETH_P_ARP,
+            hardware_type: 0x0001,
+            protocol_type: 0x0800,
+            hw_addr_len: 6,
+            proto_addr_len: 4,
+            operation: 1,
+            sender_hw_addr: hex!("02 42 ac 18 00 02"),
+            sender_proto_addr: hex!("7f 00 00 01"),
+            target_hw_addr: [0; 6],
+            target_proto_addr: hex!("7f 00 00 01"),
+        };
+        let bytes = frame.to_vec().unwrap();
+        assert_eq!(bytes.len(), 60);
+        assert_eq!(&bytes[12..22], &hex!("08 06 00 01 08 00 06 04 00 01"));
+        assert!(bytes[42..].iter().all(|b| *b == 0));
+    }
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn interface_arguments() {
+        for given in [
+            &["-i", "plc0"][..],
+            &["--iface", "plc0"],
+            &["--iface=plc0"],
+            &["-i", "eth1", "--iface=plc0"],
+        ] {
+            assert_eq!(
+                network_iface(args(given)),
+                Ok("plc0".to_string()),
+                "{:?}",
+                given
+            );
+        }
+        for given in [
+            &["-i"][..],
+            &["--iface"],
+            &["plc0"],
+            &["-x", "plc0"],
+            &["--iface", "plc0", "-v"],
+        ] {
+            assert!(network_iface(args(given)).is_err(), "{:?}", given);
+        }
+        // The only test touching the environment
+        std::env::set_var(NETWORK_IFACE_ENV, "plc1");
+        assert_eq!(network_iface(args(&[])), Ok("plc1".to_string()));
+        assert_eq!(network_iface(args(&["-i", "plc0"])), Ok("plc0".to_string()));
+        std::env::remove_var(NETWORK_IFACE_ENV);
+        assert_eq!(
+            network_iface(args(&[])),
+            Ok(DEFAULT_NETWORK_IFACE.to_string())
+        );
+    }
 }
@@ -0,0 +1,1389 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use hex_literal::hex;
+
+use crate::codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer};
+
+pub type MacAddr = [u8; 6];
+
+/// RunID chosen by the PEV for a single SLAC session
+pub type RunId = [u8; 8];
+
+/// PEV ID / EVSE ID as carried in the SLAC messages (17 bytes, VIN or all zeros)
+pub type StationId = [u8; 17];
+
+pub const BROADCAST_MAC: MacAddr = [0xff; 6];
+
+// Key types of CM_SET_KEY.REQ
+pub const KEY_TYPE_DAK: u8 = b'\x00';
+pub const KEY_TYPE_NMK: u8 = b'\x01';
+pub const KEY_TYPE_NEK: u8 = b'\x02';
+pub const KEY_TYPE_TEK: u8 = b'\x03';
+pub const KEY_TYPE_HASH_KEY: u8 = b'\x04';
+pub const KEY_TYPE_NONCE_ONLY: u8 = b'\x05';
+// Protocol IDs of CM_SET_KEY.REQ
+pub const PID_AUTHENTICATION: u8 = b'\x00';
+pub const PID_PROVISION_NEK: u8 = b'\x01';
+pub const PID_PROVISION_NMK_DAK: u8 = b'\x02';
+pub const PID_PROVISION_NMK_UKE: u8 = b'\x03';
+pub const PID_HLE: u8 = b'\x04';
+
+// Defaults of CM_SET_KEY.REQ per ISO 15118-3, except for MyNonce which is
+// random so the confirmation can be told apart from stale ones
+const CM_SET_KEY_TYPE: u8 = KEY_TYPE_NMK;
+const CM_SET_KEY_YOUR_NONCE: [u8; 4] = hex!("00 00 00 00");
+const CM_SET_KEY_PID: u8 = PID_HLE;
+const CM_SET_KEY_PRN: [u8; 2] = hex!("00 00");
+const CM_SET_KEY_PMN: u8 = b'\x00';
+const CM_SET_KEY_NEW_EKS: u8 = b'\x01';
+const CM_SET_CCO_CAPAB: u8 = b'\x00';
+// Result of CM_SET_KEY.CNF
+pub const SET_KEY_RESULT_SUCCESS: u8 = b'\x00';
+pub const SET_KEY_RESULT_FAILURE: u8 = b'\x01';
+
+// ISO 15118-3 only defines the PEV-EVSE association application
+pub const SLAC_APPLICATION_TYPE: u8 = b'\x00';
+pub const SLAC_SECURITY_TYPE: u8 = b'\x00';
+// Public key signature, adds the cipher suites to CM_SLAC_PARM
+pub const SLAC_SECURITY_TYPE_SIGNATURE: u8 = b'\x01';
+// RESP_TYPE: 0x00 sounds are answered by the HLE, 0x01 by "other GP station"
+// (the EVSE host)
+pub const SLAC_RESP_TYPE_HLE: u8 = b'\x00';
+pub const SLAC_RESP_TYPE: u8 = b'\x01';
+
+// Number of octets following the MVFLength field in CM_SLAC_MATCH.REQ/CNF
+const SLAC_MATCH_REQ_MVF_LENGTH: u16 = 0x3e;
+const SLAC_MATCH_CNF_MVF_LENGTH: u16 = 0x56;
+
+// Result of CM_ATTEN_CHAR.RSP
+pub const ATTEN_CHAR_RESULT_SUCCESS: u8 = b'\x00';
+pub const ATTEN_CHAR_RESULT_FAILURE: u8 = b'\x01';
+
+// SignalType of CM_VALIDATE, the only one defined is the PEV S2 toggle
+pub const VALIDATE_SIGNAL_TYPE: u8 = b'\x00';
+// Result of CM_VALIDATE.REQ/CNF
+pub const VALIDATE_RESULT_NOT_READY: u8 = b'\x00';
+pub const VALIDATE_RESULT_READY: u8 = b'\x01';
+pub const VALIDATE_RESULT_SUCCESS: u8 = b'\x02';
+pub const VALIDATE_RESULT_FAILURE: u8 = b'\x03';
+pub const VALIDATE_RESULT_NOT_REQUIRED: u8 = b'\x04';
+const VALIDATE_RESULTS: [u8; 5] = [
+    VALIDATE_RESULT_NOT_READY,
+    VALIDATE_RESULT_READY,
+    VALIDATE_RESULT_SUCCESS,
+    VALIDATE_RESULT_FAILURE,
+    VALIDATE_RESULT_NOT_REQUIRED,
+];
+
+/// Number of groups of the Average Attenuation profile (AAG) in HomePlug Green PHY
+pub const ATTEN_GROUPS: usize = 58;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetKeyReq {
+    key_type: u8,
+    my_nonce: [u8; 4],
+    your_nonce: [u8; 4],
+    pid: u8,
+    prn: [u8; 2],
+    pmn: u8,
+    cco_cap: u8,
+    nid: [u8; 7],
+    new_eks: u8,
+    new_key: [u8; 16],
+}
+
+impl SetKeyReq {
+    /// Request with the ISO 15118-3 defaults and a random nonce
+    pub fn new(nid: [u8; 7], new_key: [u8; 16]) -> Self {
+        SetKeyReq::builder(nid, new_key)
+            .build()
+            .expect("the defaults are consistent")
+    }
+
+    pub fn builder(nid: [u8; 7], new_key: [u8; 16]) -> SetKeyReqBuilder {
+        SetKeyReqBuilder {
+            req: SetKeyReq {
+                key_type: CM_SET_KEY_TYPE,
+                my_nonce: rand::random(),
+                your_nonce: CM_SET_KEY_YOUR_NONCE,
+                pid: CM_SET_KEY_PID,
+                prn: CM_SET_KEY_PRN,
+                pmn: CM_SET_KEY_PMN,
+                cco_cap: CM_SET_CCO_CAPAB,
+                nid,
+                new_eks: CM_SET_KEY_NEW_EKS,
+                new_key,
+            },
+        }
+    }
+
+    pub fn nid(&self) -> [u8; 7] {
+        self.nid
+    }
+
+    pub fn new_key(&self) -> [u8; 16] {
+        self.new_key
+    }
+
+    /// Nonce the modem has to echo back in CM_SET_KEY.CNF
+    pub fn my_nonce(&self) -> [u8; 4] {
+        self.my_nonce
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetKeyError {
+    UnknownKeyType(u8),
+    UnknownPid(u8),
+    /// The protocol `pid` can't deliver a key of type `key_type`
+    PidMismatch {
+        key_type: u8,
+        pid: u8,
+    },
+    /// EKS is a 4 bit field
+    InvalidEks(u8),
+}
+
+impl fmt::Display for SetKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetKeyError::UnknownKeyType(key_type) => write!(f, "unknown key type {}", key_type),
+            SetKeyError::UnknownPid(pid) => write!(f, "unknown protocol id {}", pid),
+            SetKeyError::PidMismatch { key_type, pid } => {
+                write!(
+                    f,
+                    "protocol id {} can't set a key of type {}",
+                    pid, key_type
+                )
+            }
+            SetKeyError::InvalidEks(eks) => write!(f, "invalid EKS {:#04x}", eks),
+        }
+    }
+}
+
+impl Error for SetKeyError {}
+
+/// Overrides single fields of CM_SET_KEY.REQ, `build` checks they fit together
+#[derive(Debug, Clone)]
+pub struct SetKeyReqBuilder {
+    req: SetKeyReq,
+}
+
+impl SetKeyReqBuilder {
+    pub fn key_type(mut self, key_type: u8) -> Self {
+        self.req.key_type = key_type;
+        self
+    }
+
+    pub fn my_nonce(mut self, my_nonce: [u8; 4]) -> Self {
+        self.req.my_nonce = my_nonce;
+        self
+    }
+
+    pub fn your_nonce(mut self, your_nonce: [u8; 4]) -> Self {
+        self.req.your_nonce = your_nonce;
+        self
+    }
+
+    pub fn pid(mut self, pid: u8) -> Self {
+        self.req.pid = pid;
+        self
+    }
+
+    pub fn prn(mut self, prn: [u8; 2]) -> Self {
+        self.req.prn = prn;
+        self
+    }
+
+    pub fn pmn(mut self, pmn: u8) -> Self {
+        self.req.pmn = pmn;
+        self
+    }
+
+    pub fn cco_cap(mut self, cco_cap: u8) -> Self {
+        self.req.cco_cap = cco_cap;
+        self
+    }
+
+    pub fn new_eks(mut self, new_eks: u8) -> Self {
+        self.req.new_eks = new_eks;
+        self
+    }
+
+    pub fn build(self) -> Result<SetKeyReq, SetKeyError> {
+        let req = self.req;
+        if req.key_type > KEY_TYPE_NONCE_ONLY {
+            return Err(SetKeyError::UnknownKeyType(req.key_type));
+        }
+        if req.pid > PID_HLE {
+            return Err(SetKeyError::UnknownPid(req.pid));
+        }
+        let pid_fits = match req.key_type {
+            KEY_TYPE_NMK => matches!(
+                req.pid,
+                PID_PROVISION_NMK_DAK | PID_PROVISION_NMK_UKE | PID_HLE
+            ),
+            KEY_TYPE_NEK => matches!(req.pid, PID_AUTHENTICATION | PID_PROVISION_NEK),
+            _ => true,
+        };
+        if !pid_fits {
+            return Err(SetKeyError::PidMismatch {
+                key_type: req.key_type,
+                pid: req.pid,
+            });
+        }
+        if req.new_eks > 0x0f {
+            return Err(SetKeyError::InvalidEks(req.new_eks));
+        }
+        Ok(req)
+    }
+}
+
+impl Encode for SetKeyReq {
+    fn encoded_len(&self) -> usize {
+        38
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.key_type);
+        w.bytes(&self.my_nonce);
+        w.bytes(&self.your_nonce);
+        w.u8(self.pid);
+        w.bytes(&self.prn);
+        w.u8(self.pmn);
+        w.u8(self.cco_cap);
+        w.bytes(&self.nid);
+        w.u8(self.new_eks);
+        w.bytes(&self.new_key);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for SetKeyReq {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(SetKeyReq {
+            key_type: r.u8()?,
+            my_nonce: r.array()?,
+            your_nonce: r.array()?,
+            pid: r.u8()?,
+            prn: r.array()?,
+            pmn: r.u8()?,
+            cco_cap: r.u8()?,
+            nid: r.array()?,
+            new_eks: r.u8()?,
+            new_key: r.array()?,
+        })
+    }
+}
+
+/// CM_SET_KEY.CNF, the local modem acknowledging the new NMK
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetKeyCnf {
+    pub result: u8,
+    pub my_nonce: [u8; 4],
+    /// Echo of the `my_nonce` of the request
+    pub your_nonce: [u8; 4],
+    pub pid: u8,
+    pub prn: [u8; 2],
+    pub pmn: u8,
+    pub cco_cap: u8,
+}
+
+impl Encode for SetKeyCnf {
+    fn encoded_len(&self) -> usize {
+        14
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.result);
+        w.bytes(&self.my_nonce);
+        w.bytes(&self.your_nonce);
+        w.u8(self.pid);
+        w.bytes(&self.prn);
+        w.u8(self.pmn);
+        w.u8(self.cco_cap);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for SetKeyCnf {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(SetKeyCnf {
+            result: r.u8_in("Result", &[SET_KEY_RESULT_SUCCESS, SET_KEY_RESULT_FAILURE])?,
+            my_nonce: r.array()?,
+            your_nonce: r.array()?,
+            pid: r.u8()?,
+            prn: r.array()?,
+            pmn: r.u8()?,
+            cco_cap: r.u8()?,
+        })
+    }
+}
+
+/// Average attenuation per carrier group (AAG), in dB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttenuationGroups(pub [u8; ATTEN_GROUPS]);
+
+impl Default for AttenuationGroups {
+    fn default() -> Self {
+        AttenuationGroups([0; ATTEN_GROUPS])
+    }
+}
+
+impl AttenuationGroups {
+    /// Mean attenuation over all the groups, in dB
+    pub fn mean(&self) -> f32 {
+        let sum: u32 = self.0.iter().map(|group| u32::from(*group)).sum();
+        sum as f32 / ATTEN_GROUPS as f32
+    }
+
+    /// Reads the ATTEN_PROFILE field: NumGroups followed by the groups
+    fn decode_profile(r: &mut Reader) -> Result<(u8, Self), DecodeError> {
+        let num_groups = AttenuationGroups::decode_num_groups(r)?;
+        Ok((num_groups, AttenuationGroups(r.array()?)))
+    }
+
+    /// Reads NumGroups, which can't be more than there are groups
+    fn decode_num_groups(r: &mut Reader) -> Result<u8, DecodeError> {
+        let offset = r.offset();
+        let num_groups = r.u8()?;
+        if usize::from(num_groups) > ATTEN_GROUPS {
+            return Err(DecodeError::Invalid {
+                field: "NumGroups",
+                offset,
+            });
+        }
+        Ok(num_groups)
+    }
+}
+
+/// CM_SLAC_PARM.REQ, broadcast by the PEV to start a SLAC session
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlacParmReq {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub run_id: RunId,
+    /// Cipher suites supported by the PEV, only on the wire with
+    /// `SLAC_SECURITY_TYPE_SIGNATURE`
+    pub cipher_suites: Vec<u16>,
+}
+
+impl SlacParmReq {
+    pub fn new(run_id: RunId) -> Self {
+        SlacParmReq {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            run_id,
+            cipher_suites: Vec::new(),
+        }
+    }
+
+    pub fn with_signature(run_id: RunId, cipher_suites: Vec<u16>) -> Self {
+        SlacParmReq {
+            security_type: SLAC_SECURITY_TYPE_SIGNATURE,
+            cipher_suites,
+            ..SlacParmReq::new(run_id)
+        }
+    }
+}
+
+impl Encode for SlacParmReq {
+    fn encoded_len(&self) -> usize {
+        match self.security_type {
+            SLAC_SECURITY_TYPE_SIGNATURE => 11 + 2 * self.cipher_suites.len(),
+            _ => 10,
+        }
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.bytes(&self.run_id);
+        if self.security_type == SLAC_SECURITY_TYPE_SIGNATURE {
+            // CipherSuiteSetSize is a single octet
+            let count = u8::try_from(self.cipher_suites.len()).map_err(|_| {
+                EncodeError::TooManyEntries {
+                    field: "CipherSuite",
+                    count: self.cipher_suites.len(),
+                }
+            })?;
+            w.u8(count);
+            for cipher_suite in self.cipher_suites.iter() {
+                w.u16(*cipher_suite);
+            }
+        }
+        Ok(w.finish())
+    }
+}
+
+impl Decode for SlacParmReq {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        let application_type = r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?;
+        let security_type = r.u8_in(
+            "SECURITY_TYPE",
+            &[SLAC_SECURITY_TYPE, SLAC_SECURITY_TYPE_SIGNATURE],
+        )?;
+        let run_id = r.array()?;
+        let mut cipher_suites = Vec::new();
+        if security_type == SLAC_SECURITY_TYPE_SIGNATURE {
+            for _ in 0..r.u8()? {
+                cipher_suites.push(r.u16()?);
+            }
+        }
+        Ok(SlacParmReq {
+            application_type,
+            security_type,
+            run_id,
+            cipher_suites,
+        })
+    }
+}
+
+/// CM_SLAC_PARM.CNF, the EVSE answer with the sounding parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlacParmCnf {
+    /// Destination of the sounds, always the broadcast address
+    pub msound_target: MacAddr,
+    pub num_sounds: u8,
+    // in multiples of 100 ms
+    pub time_out: u8,
+    pub resp_type: u8,
+    /// STA the attenuation results are sent to, the PEV host
+    pub forwarding_sta: MacAddr,
+    pub application_type: u8,
+    pub security_type: u8,
+    pub run_id: RunId,
+    /// Cipher suite picked by the EVSE, only on the wire with
+    /// `SLAC_SECURITY_TYPE_SIGNATURE`
+    pub cipher_suite: u16,
+}
+
+impl SlacParmCnf {
+    pub fn new(forwarding_sta: MacAddr, num_sounds: u8, time_out: u8, run_id: RunId) -> Self {
+        SlacParmCnf {
+            msound_target: BROADCAST_MAC,
+            num_sounds,
+            time_out,
+            resp_type: SLAC_RESP_TYPE,
+            forwarding_sta,
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            run_id,
+            cipher_suite: 0,
+        }
+    }
+}
+
+impl Encode for SlacParmCnf {
+    fn encoded_len(&self) -> usize {
+        match self.security_type {
+            SLAC_SECURITY_TYPE_SIGNATURE => 27,
+            _ => 25,
+        }
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.bytes(&self.msound_target);
+        w.u8(self.num_sounds);
+        w.u8(self.time_out);
+        w.u8(self.resp_type);
+        w.bytes(&self.forwarding_sta);
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.bytes(&self.run_id);
+        if self.security_type == SLAC_SECURITY_TYPE_SIGNATURE {
+            w.u16(self.cipher_suite);
+        }
+        Ok(w.finish())
+    }
+}
+
+impl Decode for SlacParmCnf {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        let msound_target = r.array()?;
+        let num_sounds = r.u8()?;
+        let time_out = r.u8()?;
+        let resp_type = r.u8_in("RESP_TYPE", &[SLAC_RESP_TYPE_HLE, SLAC_RESP_TYPE])?;
+        let forwarding_sta = r.array()?;
+        let application_type = r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?;
+        let security_type = r.u8_in(
+            "SECURITY_TYPE",
+            &[SLAC_SECURITY_TYPE, SLAC_SECURITY_TYPE_SIGNATURE],
+        )?;
+        let run_id = r.array()?;
+        let cipher_suite = match security_type {
+            SLAC_SECURITY_TYPE_SIGNATURE => r.u16()?,
+            _ => 0,
+        };
+        Ok(SlacParmCnf {
+            msound_target,
+            num_sounds,
+            time_out,
+            resp_type,
+            forwarding_sta,
+            application_type,
+            security_type,
+            run_id,
+            cipher_suite,
+        })
+    }
+}
+
+/// CM_START_ATTEN_CHAR.IND, sent by the PEV right before the sounds
+#[derive(Debug, Clone, PartialEq)]
+pub struct StartAttenCharInd {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub num_sounds: u8,
+    // in multiples of 100 ms
+    pub time_out: u8,
+    pub resp_type: u8,
+    pub forwarding_sta: MacAddr,
+    pub run_id: RunId,
+}
+
+impl StartAttenCharInd {
+    pub fn new(forwarding_sta: MacAddr, num_sounds: u8, time_out: u8, run_id: RunId) -> Self {
+        StartAttenCharInd {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            num_sounds,
+            time_out,
+            resp_type: SLAC_RESP_TYPE,
+            forwarding_sta,
+            run_id,
+        }
+    }
+}
+
+impl Encode for StartAttenCharInd {
+    fn encoded_len(&self) -> usize {
+        19
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.u8(self.num_sounds);
+        w.u8(self.time_out);
+        w.u8(self.resp_type);
+        w.bytes(&self.forwarding_sta);
+        w.bytes(&self.run_id);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for StartAttenCharInd {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(StartAttenCharInd {
+            application_type: r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?,
+            security_type: r.u8_in("SECURITY_TYPE", &[SLAC_SECURITY_TYPE])?,
+            num_sounds: r.u8()?,
+            time_out: r.u8()?,
+            resp_type: r.u8_in("RESP_TYPE", &[SLAC_RESP_TYPE_HLE, SLAC_RESP_TYPE])?,
+            forwarding_sta: r.array()?,
+            run_id: r.array()?,
+        })
+    }
+}
+
+/// CM_MNBC_SOUND.IND, one M-Node-Broadcast sound of the PEV
+#[derive(Debug, Clone, PartialEq)]
+pub struct MnbcSoundInd {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub sender_id: StationId,
+    // number of sounds still to be sent after this one
+    pub cnt: u8,
+    pub run_id: RunId,
+    pub rsvd: [u8; 8],
+    pub rnd: [u8; 16],
+}
+
+impl MnbcSoundInd {
+    pub fn new(cnt: u8, run_id: RunId, rnd: [u8; 16]) -> Self {
+        MnbcSoundInd {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            sender_id: [0; 17],
+            cnt,
+            run_id,
+            rsvd: [0; 8],
+            rnd,
+        }
+    }
+
+    /// The `num_sounds` sounds of a sounding, `cnt` counting down to zero and
+    /// each one carrying a fresh random field
+    pub fn burst(
+        num_sounds: u8,
+        run_id: RunId,
+        sender_id: StationId,
+    ) -> impl Iterator<Item = MnbcSoundInd> {
+        (0..num_sounds).rev().map(move |cnt| MnbcSoundInd {
+            sender_id,
+            ..MnbcSoundInd::new(cnt, run_id, rand::random())
+        })
+    }
+}
+
+impl Encode for MnbcSoundInd {
+    fn encoded_len(&self) -> usize {
+        52
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.bytes(&self.sender_id);
+        w.u8(self.cnt);
+        w.bytes(&self.run_id);
+        w.bytes(&self.rsvd);
+        w.bytes(&self.rnd);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for MnbcSoundInd {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(MnbcSoundInd {
+            application_type: r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?,
+            security_type: r.u8_in("SECURITY_TYPE", &[SLAC_SECURITY_TYPE])?,
+            sender_id: r.array()?,
+            cnt: r.u8()?,
+            run_id: r.array()?,
+            rsvd: r.array()?,
+            rnd: r.array()?,
+        })
+    }
+}
+
+/// CM_ATTEN_PROFILE.IND, reported by the local modem for every sound it heard
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttenProfileInd {
+    pub pev_mac: MacAddr,
+    pub num_groups: u8,
+    pub rsvd: u8,
+    pub aag: AttenuationGroups,
+}
+
+impl Encode for AttenProfileInd {
+    fn encoded_len(&self) -> usize {
+        8 + ATTEN_GROUPS
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.bytes(&self.pev_mac);
+        w.u8(self.num_groups);
+        w.u8(self.rsvd);
+        w.bytes(&self.aag.0);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for AttenProfileInd {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        let pev_mac = r.array()?;
+        // Unlike ATTEN_PROFILE there's a reserved octet before the groups
+        let num_groups = AttenuationGroups::decode_num_groups(&mut r)?;
+        Ok(AttenProfileInd {
+            pev_mac,
+            num_groups,
+            rsvd: r.u8()?,
+            aag: AttenuationGroups(r.array()?),
+        })
+    }
+}
+
+/// CM_ATTEN_CHAR.IND, the attenuation profile the EVSE computed for the PEV
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttenCharInd {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub source_address: MacAddr,
+    pub run_id: RunId,
+    pub source_id: StationId,
+    pub resp_id: StationId,
+    pub num_sounds: u8,
+    pub num_groups: u8,
+    pub aag: AttenuationGroups,
+}
+
+impl AttenCharInd {
+    pub fn new(
+        source_address: MacAddr,
+        run_id: RunId,
+        resp_id: StationId,
+        num_sounds: u8,
+        aag: AttenuationGroups,
+    ) -> Self {
+        AttenCharInd {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            source_address,
+            run_id,
+            source_id: [0; 17],
+            resp_id,
+            num_sounds,
+            num_groups: ATTEN_GROUPS as u8,
+            aag,
+        }
+    }
+}
+
+impl Encode for AttenCharInd {
+    fn encoded_len(&self) -> usize {
+        52 + ATTEN_GROUPS
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.bytes(&self.source_address);
+        w.bytes(&self.run_id);
+        w.bytes(&self.source_id);
+        w.bytes(&self.resp_id);
+        w.u8(self.num_sounds);
+        w.u8(self.num_groups);
+        w.bytes(&self.aag.0);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for AttenCharInd {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        let application_type = r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?;
+        let security_type = r.u8_in("SECURITY_TYPE", &[SLAC_SECURITY_TYPE])?;
+        let source_address = r.array()?;
+        let run_id = r.array()?;
+        let source_id = r.array()?;
+        let resp_id = r.array()?;
+        let num_sounds = r.u8()?;
+        let (num_groups, aag) = AttenuationGroups::decode_profile(&mut r)?;
+        Ok(AttenCharInd {
+            application_type,
+            security_type,
+            source_address,
+            run_id,
+            source_id,
+            resp_id,
+            num_sounds,
+            num_groups,
+            aag,
+        })
+    }
+}
+
+/// CM_ATTEN_CHAR.RSP, the PEV acknowledging the attenuation profile of an EVSE
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttenCharRsp {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub source_address: MacAddr,
+    pub run_id: RunId,
+    pub source_id: StationId,
+    pub resp_id: StationId,
+    pub result: u8,
+}
+
+impl AttenCharRsp {
+    pub fn new(ind: &AttenCharInd, result: u8) -> Self {
+        AttenCharRsp {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            source_address: ind.source_address,
+            run_id: ind.run_id,
+            source_id: ind.source_id,
+            resp_id: ind.resp_id,
+            result,
+        }
+    }
+}
+
+impl Encode for AttenCharRsp {
+    fn encoded_len(&self) -> usize {
+        51
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.bytes(&self.source_address);
+        w.bytes(&self.run_id);
+        w.bytes(&self.source_id);
+        w.bytes(&self.resp_id);
+        w.u8(self.result);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for AttenCharRsp {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(AttenCharRsp {
+            application_type: r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?,
+            security_type: r.u8_in("SECURITY_TYPE", &[SLAC_SECURITY_TYPE])?,
+            source_address: r.array()?,
+            run_id: r.array()?,
+            source_id: r.array()?,
+            resp_id: r.array()?,
+            result: r.u8_in(
+                "Result",
+                &[ATTEN_CHAR_RESULT_SUCCESS, ATTEN_CHAR_RESULT_FAILURE],
+            )?,
+        })
+    }
+}
+
+/// CM_VALIDATE.REQ, the PEV asking for, then starting, the BCB toggle
+/// validation. The first request carries a zero `timer`, the second one the
+/// toggle window in multiples of 100 ms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidateReq {
+    pub signal_type: u8,
+    pub timer: u8,
+    pub result: u8,
+}
+
+impl ValidateReq {
+    pub fn new(timer: u8) -> Self {
+        ValidateReq {
+            signal_type: VALIDATE_SIGNAL_TYPE,
+            timer,
+            result: VALIDATE_RESULT_READY,
+        }
+    }
+}
+
+impl Encode for ValidateReq {
+    fn encoded_len(&self) -> usize {
+        3
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.signal_type);
+        w.u8(self.timer);
+        w.u8(self.result);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for ValidateReq {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(ValidateReq {
+            signal_type: r.u8_in("SignalType", &[VALIDATE_SIGNAL_TYPE])?,
+            timer: r.u8()?,
+            result: r.u8_in("Result", &VALIDATE_RESULTS)?,
+        })
+    }
+}
+
+/// CM_VALIDATE.CNF, whether the EVSE takes part in the validation and, at the
+/// end of the toggle window, how many toggles it saw
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidateCnf {
+    pub signal_type: u8,
+    pub toggle_num: u8,
+    pub result: u8,
+}
+
+impl ValidateCnf {
+    pub fn new(toggle_num: u8, result: u8) -> Self {
+        ValidateCnf {
+            signal_type: VALIDATE_SIGNAL_TYPE,
+            toggle_num,
+            result,
+        }
+    }
+}
+
+impl Encode for ValidateCnf {
+    fn encoded_len(&self) -> usize {
+        3
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.signal_type);
+        w.u8(self.toggle_num);
+        w.u8(self.result);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for ValidateCnf {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(ValidateCnf {
+            signal_type: r.u8_in("SignalType", &[VALIDATE_SIGNAL_TYPE])?,
+            toggle_num: r.u8()?,
+            result: r.u8_in("Result", &VALIDATE_RESULTS)?,
+        })
+    }
+}
+
+/// CM_SLAC_MATCH.REQ, the PEV asking the chosen EVSE to join its logical network
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlacMatchReq {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub mvf_length: u16,
+    pub pev_id: StationId,
+    pub pev_mac: MacAddr,
+    pub evse_id: StationId,
+    pub evse_mac: MacAddr,
+    pub run_id: RunId,
+    pub rsvd: [u8; 8],
+}
+
+impl SlacMatchReq {
+    pub fn new(pev_mac: MacAddr, evse_mac: MacAddr, run_id: RunId) -> Self {
+        SlacMatchReq {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            mvf_length: SLAC_MATCH_REQ_MVF_LENGTH,
+            pev_id: [0; 17],
+            pev_mac,
+            evse_id: [0; 17],
+            evse_mac,
+            run_id,
+            rsvd: [0; 8],
+        }
+    }
+}
+
+impl Encode for SlacMatchReq {
+    fn encoded_len(&self) -> usize {
+        4 + usize::from(SLAC_MATCH_REQ_MVF_LENGTH)
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.u16(self.mvf_length);
+        w.bytes(&self.pev_id);
+        w.bytes(&self.pev_mac);
+        w.bytes(&self.evse_id);
+        w.bytes(&self.evse_mac);
+        w.bytes(&self.run_id);
+        w.bytes(&self.rsvd);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for SlacMatchReq {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(SlacMatchReq {
+            application_type: r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?,
+            security_type: r.u8_in("SECURITY_TYPE", &[SLAC_SECURITY_TYPE])?,
+            mvf_length: r.u16_eq("MVFLength", SLAC_MATCH_REQ_MVF_LENGTH)?,
+            pev_id: r.array()?,
+            pev_mac: r.array()?,
+            evse_id: r.array()?,
+            evse_mac: r.array()?,
+            run_id: r.array()?,
+            rsvd: r.array()?,
+        })
+    }
+}
+
+/// CM_SLAC_MATCH.CNF, delivering the NID and NMK of the EVSE network to the PEV
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlacMatchCnf {
+    pub application_type: u8,
+    pub security_type: u8,
+    pub mvf_length: u16,
+    pub pev_id: StationId,
+    pub pev_mac: MacAddr,
+    pub evse_id: StationId,
+    pub evse_mac: MacAddr,
+    pub run_id: RunId,
+    pub rsvd1: [u8; 8],
+    pub nid: [u8; 7],
+    pub rsvd2: u8,
+    pub nmk: [u8; 16],
+}
+
+impl SlacMatchCnf {
+    pub fn new(req: &SlacMatchReq, evse_id: StationId, nid: [u8; 7], nmk: [u8; 16]) -> Self {
+        SlacMatchCnf {
+            application_type: SLAC_APPLICATION_TYPE,
+            security_type: SLAC_SECURITY_TYPE,
+            mvf_length: SLAC_MATCH_CNF_MVF_LENGTH,
+            pev_id: req.pev_id,
+            pev_mac: req.pev_mac,
+            evse_id,
+            evse_mac: req.evse_mac,
+            run_id: req.run_id,
+            rsvd1: [0; 8],
+            nid,
+            rsvd2: 0,
+            nmk,
+        }
+    }
+
+    /// CM_SET_KEY.REQ programming the local modem with the very NID and NMK
+    /// this confirmation hands over, so both ends join the same network
+    pub fn set_key_req(&self) -> SetKeyReq {
+        SetKeyReq::new(self.nid, self.nmk)
+    }
+}
+
+impl Encode for SlacMatchCnf {
+    fn encoded_len(&self) -> usize {
+        4 + usize::from(SLAC_MATCH_CNF_MVF_LENGTH)
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.u8(self.application_type);
+        w.u8(self.security_type);
+        w.u16(self.mvf_length);
+        w.bytes(&self.pev_id);
+        w.bytes(&self.pev_mac);
+        w.bytes(&self.evse_id);
+        w.bytes(&self.evse_mac);
+        w.bytes(&self.run_id);
+        w.bytes(&self.rsvd1);
+        w.bytes(&self.nid);
+        w.u8(self.rsvd2);
+        w.bytes(&self.nmk);
+        Ok(w.finish())
+    }
+}
+
+impl Decode for SlacMatchCnf {
+    fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buffer);
+        Ok(SlacMatchCnf {
+            application_type: r.u8_in("APPLICATION_TYPE", &[SLAC_APPLICATION_TYPE])?,
+            security_type: r.u8_in("SECURITY_TYPE", &[SLAC_SECURITY_TYPE])?,
+            mvf_length: r.u16_eq("MVFLength", SLAC_MATCH_CNF_MVF_LENGTH)?,
+            pev_id: r.array()?,
+            pev_mac: r.array()?,
+            evse_id: r.array()?,
+            evse_mac: r.array()?,
+            run_id: r.array()?,
+            rsvd1: r.array()?,
+            nid: r.array()?,
+            rsvd2: r.u8()?,
+            nmk: r.array()?,
+        })
+    }
+}
+
+/// Every management message the SLAC state machines send or react to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    SlacParmReq(SlacParmReq),
+    SlacParmCnf(SlacParmCnf),
+    StartAttenCharInd(StartAttenCharInd),
+    MnbcSoundInd(MnbcSoundInd),
+    AttenProfileInd(AttenProfileInd),
+    AttenCharInd(AttenCharInd),
+    AttenCharRsp(AttenCharRsp),
+    ValidateReq(ValidateReq),
+    ValidateCnf(ValidateCnf),
+    SlacMatchReq(SlacMatchReq),
+    SlacMatchCnf(SlacMatchCnf),
+    SetKeyReq(SetKeyReq),
+    SetKeyCnf(SetKeyCnf),
+}
+
+impl Encode for Message {
+    fn encoded_len(&self) -> usize {
+        match self {
+            Message::SlacParmReq(m) => m.encoded_len(),
+            Message::SlacParmCnf(m) => m.encoded_len(),
+            Message::StartAttenCharInd(m) => m.encoded_len(),
+            Message::MnbcSoundInd(m) => m.encoded_len(),
+            Message::AttenProfileInd(m) => m.encoded_len(),
+            Message::AttenCharInd(m) => m.encoded_len(),
+            Message::AttenCharRsp(m) => m.encoded_len(),
+            Message::ValidateReq(m) => m.encoded_len(),
+            Message::ValidateCnf(m) => m.encoded_len(),
+            Message::SlacMatchReq(m) => m.encoded_len(),
+            Message::SlacMatchCnf(m) => m.encoded_len(),
+            Message::SetKeyReq(m) => m.encoded_len(),
+            Message::SetKeyCnf(m) => m.encoded_len(),
+        }
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        match self {
+            Message::SlacParmReq(m) => m.encode(buffer),
+            Message::SlacParmCnf(m) => m.encode(buffer),
+            Message::StartAttenCharInd(m) => m.encode(buffer),
+            Message::MnbcSoundInd(m) => m.encode(buffer),
+            Message::AttenProfileInd(m) => m.encode(buffer),
+            Message::AttenCharInd(m) => m.encode(buffer),
+            Message::AttenCharRsp(m) => m.encode(buffer),
+            Message::ValidateReq(m) => m.encode(buffer),
+            Message::ValidateCnf(m) => m.encode(buffer),
+            Message::SlacMatchReq(m) => m.encode(buffer),
+            Message::SlacMatchCnf(m) => m.encode(buffer),
+            Message::SetKeyReq(m) => m.encode(buffer),
+            Message::SetKeyCnf(m) => m.encode(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_key_req_layout() {
+        let req = SetKeyReq::builder(hex!("01 02 03 04 05 06 07"), [0x5a; 16])
+            .my_nonce(hex!("aa aa aa aa"))
+            .build()
+            .unwrap();
+        let bytes = req.to_vec().unwrap();
+        assert_eq!(
+            &bytes[..22],
+            &hex!("01 aa aa aa aa 00 00 00 00 04 00 00 00 00 01 02 03 04 05 06 07 01")
+        );
+        assert_eq!(&bytes[22..], &[0x5a; 16]);
+        assert_eq!(SetKeyReq::decode(&bytes).unwrap(), req);
+    }
+
+    #[test]
+    fn set_key_req_builder_checks_combinations() {
+        let first = SetKeyReq::new([0; 7], [0; 16]);
+        let second = SetKeyReq::new([0; 7], [0; 16]);
+        assert_ne!(first.my_nonce(), second.my_nonce());
+
+        let builder = || SetKeyReq::builder([0; 7], [0; 16]);
+        assert_eq!(
+            builder().key_type(0x06).build(),
+            Err(SetKeyError::UnknownKeyType(0x06))
+        );
+        assert_eq!(
+            builder().pid(0x05).build(),
+            Err(SetKeyError::UnknownPid(0x05))
+        );
+        assert_eq!(
+            builder().pid(PID_AUTHENTICATION).build(),
+            Err(SetKeyError::PidMismatch {
+                key_type: KEY_TYPE_NMK,
+                pid: PID_AUTHENTICATION
+            })
+        );
+        assert_eq!(
+            builder().new_eks(0x10).build(),
+            Err(SetKeyError::InvalidEks(0x10))
+        );
+        let nek = builder()
+            .key_type(KEY_TYPE_NEK)
+            .pid(PID_PROVISION_NEK)
+            .build()
+            .unwrap();
+        assert_eq!(nek.to_vec().unwrap()[9], PID_PROVISION_NEK);
+    }
+
+    #[test]
+    fn truncated_set_key_req() {
+        let bytes = SetKeyReq::new([0; 7], [0; 16]).to_vec().unwrap();
+        assert_eq!(
+            SetKeyReq::decode(&bytes[..30]),
+            Err(DecodeError::Truncated {
+                offset: 30,
+                needed: 8
+            })
+        );
+        assert_eq!(
+            SetKeyReq::new([0; 7], [0; 16]).encode(&mut [0; 10]),
+            Err(EncodeError::BufferTooSmall {
+                needed: 38,
+                available: 10
+            })
+        );
+    }
+
+    #[test]
+    fn slac_parm_round_trips() {
+        let run_id = hex!("01 02 03 04 05 06 07 08");
+        let req = SlacParmReq::new(run_id);
+        let bytes = req.to_vec().unwrap();
+        assert_eq!(bytes, hex!("00 00 01 02 03 04 05 06 07 08"));
+        assert_eq!(SlacParmReq::decode(&bytes).unwrap(), req);
+
+        let signed = SlacParmReq::with_signature(run_id, vec![0x0001, 0x0102]);
+        let bytes = signed.to_vec().unwrap();
+        assert_eq!(&bytes[10..], &hex!("02 01 00 02 01"));
+        assert_eq!(SlacParmReq::decode(&bytes).unwrap(), signed);
+
+        let cnf = SlacParmCnf::new([0x02, 0, 0, 0, 0, 0x01], 10, 6, run_id);
+        let bytes = cnf.to_vec().unwrap();
+        assert_eq!(bytes.len(), 25);
+        assert_eq!(&bytes[..9], &hex!("ff ff ff ff ff ff 0a 06 01"));
+        assert_eq!(SlacParmCnf::decode(&bytes).unwrap(), cnf);
+    }
+
+    #[test]
+    fn slac_parm_field_checks() {
+        let mut cnf = SlacParmCnf::new([0; 6], 10, 6, [0; 8]);
+        cnf.resp_type = SLAC_RESP_TYPE_HLE;
+        let mut bytes = cnf.to_vec().unwrap();
+        assert_eq!(SlacParmCnf::decode(&bytes).unwrap(), cnf);
+        bytes[15] = 0x02;
+        assert_eq!(
+            SlacParmCnf::decode(&bytes),
+            Err(DecodeError::Invalid {
+                field: "APPLICATION_TYPE",
+                offset: 15
+            })
+        );
+
+        // Without a signature the cipher suites never reach the wire
+        let mut req = SlacParmReq::new([0; 8]);
+        req.cipher_suites = vec![0; 256];
+        assert_eq!(req.to_vec().unwrap().len(), 10);
+        req.security_type = SLAC_SECURITY_TYPE_SIGNATURE;
+        assert_eq!(
+            req.to_vec(),
+            Err(EncodeError::TooManyEntries {
+                field: "CipherSuite",
+                count: 256
+            })
+        );
+    }
+
+    #[test]
+    fn sounding_burst_counts_down() {
+        let run_id = [1; 8];
+        let sounds: Vec<_> = MnbcSoundInd::burst(3, run_id, [7; 17]).collect();
+        let cnts: Vec<_> = sounds.iter().map(|sound| sound.cnt).collect();
+        assert_eq!(cnts, [2, 1, 0]);
+        for sound in &sounds {
+            assert_eq!((sound.sender_id, sound.run_id), ([7; 17], run_id));
+        }
+        assert_ne!(sounds[0].rnd, sounds[1].rnd);
+
+        let bytes = sounds[0].to_vec().unwrap();
+        assert_eq!(bytes.len(), 52);
+        assert_eq!(bytes[19], 2);
+        assert_eq!(MnbcSoundInd::decode(&bytes).unwrap(), sounds[0]);
+    }
+
+    #[test]
+    fn start_atten_char_ind_accepts_both_resp_types() {
+        let mut ind = StartAttenCharInd::new([0x02, 0, 0, 0, 0, 0x01], 10, 6, [1; 8]);
+        for resp_type in [SLAC_RESP_TYPE_HLE, SLAC_RESP_TYPE].iter() {
+            ind.resp_type = *resp_type;
+            let bytes = ind.to_vec().unwrap();
+            assert_eq!(StartAttenCharInd::decode(&bytes).unwrap(), ind);
+        }
+        let mut bytes = ind.to_vec().unwrap();
+        bytes[4] = 0x02;
+        assert_eq!(
+            StartAttenCharInd::decode(&bytes),
+            Err(DecodeError::Invalid {
+                field: "RESP_TYPE",
+                offset: 4
+            })
+        );
+    }
+
+    #[test]
+    fn atten_char_round_trips() {
+        let aag = AttenuationGroups([25; ATTEN_GROUPS]);
+        let ind = AttenCharInd::new([0x02, 0, 0, 0, 0, 0x01], [1; 8], [3; 17], 10, aag);
+        let bytes = ind.to_vec().unwrap();
+        assert_eq!(bytes.len(), 110);
+        assert_eq!(&bytes[50..53], &[10, ATTEN_GROUPS as u8, 25]);
+        assert_eq!(AttenCharInd::decode(&bytes).unwrap(), ind);
+
+        let rsp = AttenCharRsp::new(&ind, ATTEN_CHAR_RESULT_SUCCESS);
+        let bytes = rsp.to_vec().unwrap();
+        assert_eq!(bytes.len(), 51);
+        assert_eq!(AttenCharRsp::decode(&bytes).unwrap(), rsp);
+    }
+
+    #[test]
+    fn atten_char_ind_header_is_checked() {
+        let aag = AttenuationGroups([25; ATTEN_GROUPS]);
+        let ind = AttenCharInd::new([0x02, 0, 0, 0, 0, 0x01], [1; 8], [0; 17], 10, aag);
+        for (offset, field) in [(0, "APPLICATION_TYPE"), (1, "SECURITY_TYPE")].iter() {
+            let mut bytes = ind.to_vec().unwrap();
+            bytes[*offset] = 0x01;
+            assert_eq!(
+                AttenCharInd::decode(&bytes),
+                Err(DecodeError::Invalid {
+                    field,
+                    offset: *offset
+                })
+            );
+        }
+        let profile_ind = AttenProfileInd {
+            pev_mac: [0x02, 0, 0, 0, 0, 0x01],
+            num_groups: ATTEN_GROUPS as u8 + 1,
+            rsvd: 0,
+            aag,
+        };
+        assert_eq!(
+            AttenProfileInd::decode(&profile_ind.to_vec().unwrap()),
+            Err(DecodeError::Invalid {
+                field: "NumGroups",
+                offset: 6
+            })
+        );
+    }
+
+    #[test]
+    fn slac_match_cnf_hands_over_nid_and_nmk() {
+        let nid = [0x33; 7];
+        let nmk = [0x44; 16];
+        let req = SlacMatchReq::new([0x02, 0, 0, 0, 0, 0x01], [0x02, 0, 0, 0, 0, 0x02], [1; 8]);
+        let bytes = req.to_vec().unwrap();
+        assert_eq!(bytes.len(), 66);
+        assert_eq!(SlacMatchReq::decode(&bytes).unwrap(), req);
+
+        let cnf = SlacMatchCnf::new(&req, [0; 17], nid, nmk);
+        let payload = cnf.to_vec().unwrap();
+        assert_eq!(payload.len(), 90);
+        assert_eq!(payload[2..4], [0x56, 0x00]);
+        assert_eq!(payload[66..73], nid);
+        assert_eq!(payload[74..90], nmk);
+        assert_eq!(SlacMatchCnf::decode(&payload).unwrap(), cnf);
+        let set_key_req = cnf.set_key_req();
+        assert_eq!((set_key_req.nid(), set_key_req.new_key()), (nid, nmk));
+    }
+
+    #[test]
+    fn slac_match_mvf_length_is_checked() {
+        let req = SlacMatchReq::new([0; 6], [0; 6], [1; 8]);
+        let mut bytes = req.to_vec().unwrap();
+        bytes[2] = 0x56;
+        assert_eq!(
+            SlacMatchReq::decode(&bytes),
+            Err(DecodeError::Invalid {
+                field: "MVFLength",
+                offset: 2
+            })
+        );
+    }
+}
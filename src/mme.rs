@@ -0,0 +1,378 @@
+//! HomePlug AV management message (MME) framing
+//!
+//! An MME on the wire is an Ethernet frame with ethertype 0x88E1, followed by
+//! the MMV, the little-endian MMTYPE and, from HomePlug AV 1.1 on, the
+//! fragmentation info. The payload is one of the structs in `messages`.
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use crate::codec::{Decode, DecodeError, Encode, EncodeError, Writer};
+use crate::messages::{
+    AttenCharInd, AttenCharRsp, AttenProfileInd, MacAddr, Message, MnbcSoundInd, SetKeyCnf,
+    SetKeyReq, SlacMatchCnf, SlacMatchReq, SlacParmCnf, SlacParmReq, StartAttenCharInd,
+    ValidateCnf, ValidateReq,
+};
+
+pub const ETH_P_HOMEPLUG_AV: u16 = 0x88e1;
+const ETH_P_8021Q: u16 = 0x8100;
+// Minimum Ethernet frame length, without the FCS
+const ETH_ZLEN: usize = 60;
+
+/// HomePlug AV 1.0, no fragmentation info in the header
+pub const MMV_HPAV_1_0: u8 = 0x00;
+/// HomePlug AV 1.1 / Green PHY, used by ISO 15118-3
+pub const MMV_HPAV_1_1: u8 = 0x01;
+
+// Base MMTYPEs, the two least significant bits select the sub-type
+pub const CM_SET_KEY: u16 = 0x6008;
+pub const CM_SLAC_PARM: u16 = 0x6064;
+pub const CM_START_ATTEN_CHAR: u16 = 0x6068;
+pub const CM_ATTEN_CHAR: u16 = 0x606c;
+pub const CM_MNBC_SOUND: u16 = 0x6074;
+pub const CM_VALIDATE: u16 = 0x6078;
+pub const CM_SLAC_MATCH: u16 = 0x607c;
+pub const CM_ATTEN_PROFILE: u16 = 0x6084;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubType {
+    Req = 0b00,
+    Cnf = 0b01,
+    Ind = 0b10,
+    Rsp = 0b11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmType(pub u16);
+
+impl MmType {
+    pub fn new(base: u16, sub_type: SubType) -> Self {
+        MmType((base & !0b11) | sub_type as u16)
+    }
+
+    pub fn base(self) -> u16 {
+        self.0 & !0b11
+    }
+
+    pub fn sub_type(self) -> SubType {
+        match self.0 & 0b11 {
+            0b00 => SubType::Req,
+            0b01 => SubType::Cnf,
+            0b10 => SubType::Ind,
+            _ => SubType::Rsp,
+        }
+    }
+}
+
+impl fmt::Display for MmType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#06x}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmeError {
+    Truncated { len: usize, needed: usize },
+    UnexpectedEtherType(u16),
+    UnsupportedMmv(u8),
+    UnknownMmType(MmType),
+    Payload { mmtype: MmType, error: DecodeError },
+}
+
+impl fmt::Display for MmeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MmeError::Truncated { len, needed } => {
+                write!(f, "frame of {} bytes, at least {} needed", len, needed)
+            }
+            MmeError::UnexpectedEtherType(ether_type) => {
+                write!(f, "unexpected ethertype {:#06x}", ether_type)
+            }
+            MmeError::UnsupportedMmv(mmv) => write!(f, "unsupported MMV {:#04x}", mmv),
+            MmeError::UnknownMmType(mmtype) => write!(f, "unknown MMTYPE {}", mmtype),
+            MmeError::Payload { mmtype, error } => {
+                write!(f, "MMTYPE {} payload: {}", mmtype, error)
+            }
+        }
+    }
+}
+
+impl Error for MmeError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmeHeader {
+    pub destination: MacAddr,
+    pub source: MacAddr,
+    /// 802.1Q tag control information, if the frame is tagged
+    pub vlan_tci: Option<u16>,
+    pub mmv: u8,
+    pub mmtype: MmType,
+    /// Number of fragments and fragment number, one nibble each
+    pub fmi: u8,
+    /// Fragmentation message sequence number
+    pub fmsn: u8,
+}
+
+impl MmeHeader {
+    pub fn new(destination: MacAddr, source: MacAddr, mmtype: MmType) -> Self {
+        MmeHeader {
+            destination,
+            source,
+            vlan_tci: None,
+            mmv: MMV_HPAV_1_1,
+            mmtype,
+            fmi: 0,
+            fmsn: 0,
+        }
+    }
+
+    /// Parses the header, returning it along with the remaining payload
+    pub fn parse(frame: &[u8]) -> Result<(Self, &[u8]), MmeError> {
+        let truncated = |needed| MmeError::Truncated {
+            len: frame.len(),
+            needed,
+        };
+        if frame.len() < 17 {
+            return Err(truncated(17));
+        }
+        let destination: MacAddr = frame[0..6].try_into().unwrap();
+        let source: MacAddr = frame[6..12].try_into().unwrap();
+        let mut offset = 12;
+        let mut ether_type = u16::from_be_bytes([frame[12], frame[13]]);
+        let mut vlan_tci = None;
+        if ether_type == ETH_P_8021Q {
+            if frame.len() < 21 {
+                return Err(truncated(21));
+            }
+            vlan_tci = Some(u16::from_be_bytes([frame[14], frame[15]]));
+            ether_type = u16::from_be_bytes([frame[16], frame[17]]);
+            offset += 4;
+        }
+        if ether_type != ETH_P_HOMEPLUG_AV {
+            return Err(MmeError::UnexpectedEtherType(ether_type));
+        }
+        offset += 2;
+        let mmv = frame[offset];
+        let mmtype = MmType(u16::from_le_bytes([frame[offset + 1], frame[offset + 2]]));
+        offset += 3;
+        let (fmi, fmsn) = match mmv {
+            MMV_HPAV_1_0 => (0, 0),
+            MMV_HPAV_1_1 => {
+                if frame.len() < offset + 2 {
+                    return Err(truncated(offset + 2));
+                }
+                offset += 2;
+                (frame[offset - 2], frame[offset - 1])
+            }
+            mmv => return Err(MmeError::UnsupportedMmv(mmv)),
+        };
+        let header = MmeHeader {
+            destination,
+            source,
+            vlan_tci,
+            mmv,
+            mmtype,
+            fmi,
+            fmsn,
+        };
+        Ok((header, &frame[offset..]))
+    }
+}
+
+/// A complete management message: header plus typed payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mme {
+    pub header: MmeHeader,
+    pub message: Message,
+}
+
+impl Mme {
+    pub fn new(destination: MacAddr, source: MacAddr, message: Message) -> Self {
+        Mme {
+            header: MmeHeader::new(destination, source, mmtype_of(&message)),
+            message,
+        }
+    }
+
+    pub fn from_bytes(frame: &[u8]) -> Result<Self, MmeError> {
+        let (header, payload) = MmeHeader::parse(frame)?;
+        let message = message_from(header.mmtype, payload)?;
+        Ok(Mme { header, message })
+    }
+}
+
+impl Encode for MmeHeader {
+    fn encoded_len(&self) -> usize {
+        let vlan = if self.vlan_tci.is_some() { 4 } else { 0 };
+        let fmi = if self.mmv == MMV_HPAV_1_0 { 0 } else { 2 };
+        14 + vlan + 3 + fmi
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut w = Writer::new(buffer, self.encoded_len())?;
+        w.bytes(&self.destination);
+        w.bytes(&self.source);
+        if let Some(tci) = self.vlan_tci {
+            w.bytes(&ETH_P_8021Q.to_be_bytes());
+            w.bytes(&tci.to_be_bytes());
+        }
+        w.bytes(&ETH_P_HOMEPLUG_AV.to_be_bytes());
+        w.u8(self.mmv);
+        w.u16(self.mmtype.0);
+        if self.mmv != MMV_HPAV_1_0 {
+            w.u8(self.fmi);
+            w.u8(self.fmsn);
+        }
+        Ok(w.finish())
+    }
+}
+
+/// The whole frame, padded to the minimum Ethernet frame length
+impl Encode for Mme {
+    fn encoded_len(&self) -> usize {
+        let len = self.header.encoded_len() + self.message.encoded_len();
+        len.max(ETH_ZLEN)
+    }
+
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, EncodeError> {
+        let len = self.encoded_len();
+        if buffer.len() < len {
+            return Err(EncodeError::BufferTooSmall {
+                needed: len,
+                available: buffer.len(),
+            });
+        }
+        let header_len = self.header.encode(buffer)?;
+        let payload_len = self.message.encode(&mut buffer[header_len..])?;
+        buffer[header_len + payload_len..len].fill(0);
+        Ok(len)
+    }
+}
+
+pub fn mmtype_of(message: &Message) -> MmType {
+    match message {
+        Message::SlacParmReq(_) => MmType::new(CM_SLAC_PARM, SubType::Req),
+        Message::SlacParmCnf(_) => MmType::new(CM_SLAC_PARM, SubType::Cnf),
+        Message::StartAttenCharInd(_) => MmType::new(CM_START_ATTEN_CHAR, SubType::Ind),
+        Message::MnbcSoundInd(_) => MmType::new(CM_MNBC_SOUND, SubType::Ind),
+        Message::AttenProfileInd(_) => MmType::new(CM_ATTEN_PROFILE, SubType::Ind),
+        Message::AttenCharInd(_) => MmType::new(CM_ATTEN_CHAR, SubType::Ind),
+        Message::AttenCharRsp(_) => MmType::new(CM_ATTEN_CHAR, SubType::Rsp),
+        Message::ValidateReq(_) => MmType::new(CM_VALIDATE, SubType::Req),
+        Message::ValidateCnf(_) => MmType::new(CM_VALIDATE, SubType::Cnf),
+        Message::SlacMatchReq(_) => MmType::new(CM_SLAC_MATCH, SubType::Req),
+        Message::SlacMatchCnf(_) => MmType::new(CM_SLAC_MATCH, SubType::Cnf),
+        Message::SetKeyReq(_) => MmType::new(CM_SET_KEY, SubType::Req),
+        Message::SetKeyCnf(_) => MmType::new(CM_SET_KEY, SubType::Cnf),
+    }
+}
+
+fn message_from(mmtype: MmType, payload: &[u8]) -> Result<Message, MmeError> {
+    let invalid = |error| MmeError::Payload { mmtype, error };
+    let message = match (mmtype.base(), mmtype.sub_type()) {
+        (CM_SLAC_PARM, SubType::Req) => {
+            Message::SlacParmReq(SlacParmReq::decode(payload).map_err(invalid)?)
+        }
+        (CM_SLAC_PARM, SubType::Cnf) => {
+            Message::SlacParmCnf(SlacParmCnf::decode(payload).map_err(invalid)?)
+        }
+        (CM_START_ATTEN_CHAR, SubType::Ind) => {
+            Message::StartAttenCharInd(StartAttenCharInd::decode(payload).map_err(invalid)?)
+        }
+        (CM_MNBC_SOUND, SubType::Ind) => {
+            Message::MnbcSoundInd(MnbcSoundInd::decode(payload).map_err(invalid)?)
+        }
+        (CM_ATTEN_PROFILE, SubType::Ind) => {
+            Message::AttenProfileInd(AttenProfileInd::decode(payload).map_err(invalid)?)
+        }
+        (CM_ATTEN_CHAR, SubType::Ind) => {
+            Message::AttenCharInd(AttenCharInd::decode(payload).map_err(invalid)?)
+        }
+        (CM_ATTEN_CHAR, SubType::Rsp) => {
+            Message::AttenCharRsp(AttenCharRsp::decode(payload).map_err(invalid)?)
+        }
+        (CM_VALIDATE, SubType::Req) => {
+            Message::ValidateReq(ValidateReq::decode(payload).map_err(invalid)?)
+        }
+        (CM_VALIDATE, SubType::Cnf) => {
+            Message::ValidateCnf(ValidateCnf::decode(payload).map_err(invalid)?)
+        }
+        (CM_SLAC_MATCH, SubType::Req) => {
+            Message::SlacMatchReq(SlacMatchReq::decode(payload).map_err(invalid)?)
+        }
+        (CM_SLAC_MATCH, SubType::Cnf) => {
+            Message::SlacMatchCnf(SlacMatchCnf::decode(payload).map_err(invalid)?)
+        }
+        (CM_SET_KEY, SubType::Req) => {
+            Message::SetKeyReq(SetKeyReq::decode(payload).map_err(invalid)?)
+        }
+        (CM_SET_KEY, SubType::Cnf) => {
+            Message::SetKeyCnf(SetKeyCnf::decode(payload).map_err(invalid)?)
+        }
+        _ => return Err(MmeError::UnknownMmType(mmtype)),
+    };
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::SetKeyReq;
+
+    const MODEM_MAC: MacAddr = [0x00, 0xb0, 0x52, 0, 0, 0x01];
+    const HOST_MAC: MacAddr = [0x02, 0, 0, 0, 0, 0x01];
+
+    #[test]
+    fn set_key_req_round_trips() {
+        let req = SetKeyReq::new([0x33; 7], [0x44; 16]);
+        let frame = Mme::new(MODEM_MAC, HOST_MAC, Message::SetKeyReq(req))
+            .to_vec()
+            .unwrap();
+        assert_eq!(frame.len(), ETH_ZLEN);
+        assert_eq!(&frame[12..19], &[0x88, 0xe1, 0x01, 0x08, 0x60, 0x00, 0x00]);
+        let mme = Mme::from_bytes(&frame).unwrap();
+        assert_eq!(
+            mme.header,
+            MmeHeader::new(MODEM_MAC, HOST_MAC, MmType(0x6008))
+        );
+        assert_eq!(mme.to_vec().unwrap(), frame);
+    }
+
+    #[test]
+    fn parses_tagged_hpav_1_0_header() {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&MODEM_MAC);
+        frame.extend_from_slice(&HOST_MAC);
+        frame.extend_from_slice(&[0x81, 0x00, 0x20, 0x05, 0x88, 0xe1, 0x00, 0x65, 0x60]);
+        let (header, payload) = MmeHeader::parse(&frame).unwrap();
+        assert_eq!(header.vlan_tci, Some(0x2005));
+        assert_eq!(header.mmv, MMV_HPAV_1_0);
+        assert_eq!(header.mmtype, MmType::new(CM_SLAC_PARM, SubType::Cnf));
+        assert!(payload.is_empty());
+
+        assert_eq!(header.to_vec().unwrap(), frame);
+    }
+
+    #[test]
+    fn explicit_errors() {
+        let mut frame = vec![0; ETH_ZLEN];
+        frame[12..19].copy_from_slice(&[0x88, 0xe1, 0x01, 0xa0, 0xa0, 0x00, 0x00]);
+        assert_eq!(
+            Mme::from_bytes(&frame),
+            Err(MmeError::UnknownMmType(MmType(0xa0a0)))
+        );
+        assert_eq!(
+            Mme::from_bytes(&frame[..16]),
+            Err(MmeError::Truncated {
+                len: 16,
+                needed: 17
+            })
+        );
+        frame[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert_eq!(
+            Mme::from_bytes(&frame),
+            Err(MmeError::UnexpectedEtherType(0x0806))
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Link monitoring over rtnetlink
+//!
+//! The PLC modem is usually a USB or SPI device, it can lose its carrier or
+//! vanish and come back with another ifindex. `LinkMonitor` follows one
+//! interface by name through RTM_NEWLINK/RTM_DELLINK so the owner of the
+//! `RawSocket` knows when to reset the SLAC session and reopen the socket.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::error::{Error, Result};
+use crate::interface::{Interface, InterfaceFlags};
+use crate::socket::validate_ifname;
+
+const RTMGRP_LINK: u32 = 0x1;
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const IFLA_IFNAME: u16 = 3;
+const NLMSG_HDRLEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const RTA_HDRLEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The interface showed up, the socket has to be reopened on `index`
+    Added { index: u32 },
+    /// The interface is gone, along with anything bound to it
+    Removed { index: u32 },
+    /// The carrier came back or got lost, a running session is void
+    Carrier { index: u32, up: bool },
+}
+
+/// One RTM_NEWLINK or RTM_DELLINK
+#[derive(Debug, Clone, PartialEq)]
+struct LinkMessage {
+    kind: u16,
+    index: u32,
+    flags: InterfaceFlags,
+    name: Option<String>,
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn ne_u16(buffer: &[u8]) -> u16 {
+    u16::from_ne_bytes([buffer[0], buffer[1]])
+}
+
+fn ne_u32(buffer: &[u8]) -> u32 {
+    u32::from_ne_bytes([buffer[0], buffer[1], buffer[2], buffer[3]])
+}
+
+/// Link messages in one netlink datagram, anything else is skipped
+fn parse_link_messages(mut buffer: &[u8]) -> Vec<LinkMessage> {
+    let mut messages = Vec::new();
+    while buffer.len() >= NLMSG_HDRLEN {
+        let len = ne_u32(buffer) as usize;
+        if len < NLMSG_HDRLEN || len > buffer.len() {
+            break;
+        }
+        let kind = ne_u16(&buffer[4..]);
+        let payload = &buffer[NLMSG_HDRLEN..len];
+        if (kind == RTM_NEWLINK || kind == RTM_DELLINK) && payload.len() >= IFINFOMSG_LEN {
+            messages.push(LinkMessage {
+                kind,
+                index: ne_u32(&payload[4..]),
+                flags: InterfaceFlags(ne_u32(&payload[8..])),
+                name: link_name(&payload[IFINFOMSG_LEN..]),
+            });
+        }
+        buffer = &buffer[align(len).min(buffer.len())..];
+    }
+    messages
+}
+
+fn link_name(mut attributes: &[u8]) -> Option<String> {
+    while attributes.len() >= RTA_HDRLEN {
+        let len = usize::from(ne_u16(attributes));
+        if len < RTA_HDRLEN || len > attributes.len() {
+            return None;
+        }
+        if ne_u16(&attributes[2..]) == IFLA_IFNAME {
+            let name = &attributes[RTA_HDRLEN..len];
+            let name = name.split(|b| *b == 0).next().unwrap_or_default();
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+    None
+}
+
+/// Last known state of the followed interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Link {
+    index: u32,
+    carrier: bool,
+}
+
+pub struct LinkMonitor {
+    fd: OwnedFd,
+    name: String,
+    link: Option<Link>,
+}
+
+impl LinkMonitor {
+    /// Follows `ifname`, which doesn't have to exist yet
+    pub fn open(ifname: &str) -> Result<Self> {
+        validate_ifname(ifname)?;
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::os("opening netlink socket"));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut sockaddr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        sockaddr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        sockaddr.nl_groups = RTMGRP_LINK;
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &sockaddr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of_val(&sockaddr) as libc::socklen_t,
+            )
+        };
+        if res == -1 {
+            return Err(Error::os("binding netlink socket"));
+        }
+        let mut monitor = LinkMonitor {
+            fd,
+            name: ifname.to_string(),
+            link: None,
+        };
+        monitor.resync()?;
+        Ok(monitor)
+    }
+
+    /// Index of the interface, if it's there
+    pub fn index(&self) -> Option<u32> {
+        self.link.map(|link| link.index)
+    }
+
+    /// Blocks until the kernel reports a link change, returning what it means
+    /// for the followed interface. That may well be nothing.
+    pub fn recv(&mut self) -> Result<Vec<LinkEvent>> {
+        let mut buffer = [0u8; 32 * 1024];
+        let len = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if len == -1 {
+            let source = io::Error::last_os_error();
+            // The kernel dropped notifications, ask for the current state
+            if source.raw_os_error() == Some(libc::ENOBUFS) {
+                let before = self.link;
+                self.resync()?;
+                return Ok(self.changes(before));
+            }
+            return Err(Error::Os {
+                action: "receiving link events",
+                source,
+            });
+        }
+        let mut events = Vec::new();
+        for message in parse_link_messages(&buffer[..len as usize]) {
+            if message.name.as_deref() != Some(self.name.as_str()) {
+                continue;
+            }
+            let before = self.link;
+            self.link = match message.kind {
+                RTM_NEWLINK => Some(Link {
+                    index: message.index,
+                    carrier: message.flags.is_up() && message.flags.has_carrier(),
+                }),
+                _ => None,
+            };
+            events.extend(self.changes(before));
+        }
+        Ok(events)
+    }
+
+    fn resync(&mut self) -> Result<()> {
+        self.link = match Interface::query(&self.name) {
+            Ok(interface) => Some(Link {
+                index: interface.index,
+                carrier: interface.flags.is_up() && interface.flags.has_carrier(),
+            }),
+            Err(Error::NoSuchInterface(_)) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(())
+    }
+
+    fn changes(&self, before: Option<Link>) -> Vec<LinkEvent> {
+        match (before, self.link) {
+            (Some(old), Some(new)) if old.index == new.index => {
+                if old.carrier == new.carrier {
+                    vec![]
+                } else {
+                    vec![LinkEvent::Carrier {
+                        index: new.index,
+                        up: new.carrier,
+                    }]
+                }
+            }
+            (old, Some(new)) => {
+                let mut events: Vec<LinkEvent> = old
+                    .map(|old| LinkEvent::Removed { index: old.index })
+                    .into_iter()
+                    .collect();
+                events.push(LinkEvent::Added { index: new.index });
+                if new.carrier {
+                    events.push(LinkEvent::Carrier {
+                        index: new.index,
+                        up: true,
+                    });
+                }
+                events
+            }
+            (Some(old), None) => vec![LinkEvent::Removed { index: old.index }],
+            (None, None) => vec![],
+        }
+    }
+}
+
+impl AsRawFd for LinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn newlink(kind: u16, index: u32, flags: u32, name: &str) -> Vec<u8> {
+        let mut attribute = Vec::new();
+        let rta_len = RTA_HDRLEN + name.len() + 1;
+        attribute.extend_from_slice(&(rta_len as u16).to_ne_bytes());
+        attribute.extend_from_slice(&IFLA_IFNAME.to_ne_bytes());
+        attribute.extend_from_slice(name.as_bytes());
+        attribute.resize(align(rta_len), 0);
+        let len = NLMSG_HDRLEN + IFINFOMSG_LEN + attribute.len();
+        let mut message = Vec::new();
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&index.to_ne_bytes());
+        message.extend_from_slice(&flags.to_ne_bytes());
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&attribute);
+        message
+    }
+
+    #[test]
+    fn parses_link_messages() {
+        let mut datagram = newlink(RTM_NEWLINK, 7, 0x11043, "plc0");
+        datagram.extend(newlink(RTM_DELLINK, 3, 0, "eth1"));
+        let messages = parse_link_messages(&datagram);
+        assert_eq!(
+            messages,
+            vec![
+                LinkMessage {
+                    kind: RTM_NEWLINK,
+                    index: 7,
+                    flags: InterfaceFlags(0x11043),
+                    name: Some("plc0".to_string()),
+                },
+                LinkMessage {
+                    kind: RTM_DELLINK,
+                    index: 3,
+                    flags: InterfaceFlags(0),
+                    name: Some("eth1".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_datagram() {
+        let datagram = newlink(RTM_NEWLINK, 7, 0, "plc0");
+        assert!(parse_link_messages(&datagram[..20]).is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+//! PACKET_MMAP receive ring
+//!
+//! With TPACKET_V3 the kernel fills blocks of a ring shared with the process
+//! and hands a block over once it's full or its timeout expired. A busy tap
+//! gets one wakeup per block instead of a syscall per frame, and a frame is
+//! only cut short if it doesn't fit into `frame_size`.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{Error, Result};
+use crate::socket::RawSocket;
+
+// enum tpacket_versions in linux/if_packet.h, not exported by libc
+const TPACKET_V3: libc::c_int = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RingConfig {
+    /// Size of a block, a multiple of the page size
+    pub block_size: u32,
+    pub block_count: u32,
+    /// Largest frame kept whole, headers included
+    pub frame_size: u32,
+    /// A block that isn't full is handed over after this long
+    pub block_timeout: Duration,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        RingConfig {
+            block_size: 1 << 17,
+            block_count: 8,
+            frame_size: 1 << 11,
+            block_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RingConfig {
+    fn request(&self) -> Result<libc::tpacket_req3> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+        if self.block_size == 0 || self.block_size % page_size != 0 {
+            return Err(Error::InvalidRingConfig(
+                "block size isn't a multiple of the page size",
+            ));
+        }
+        if self.block_count == 0 {
+            return Err(Error::InvalidRingConfig("no blocks"));
+        }
+        if (self.frame_size as usize) < libc::TPACKET3_HDRLEN
+            || (self.frame_size as usize) % libc::TPACKET_ALIGNMENT != 0
+            || self.frame_size > self.block_size
+        {
+            return Err(Error::InvalidRingConfig("frame size doesn't fit"));
+        }
+        let timeout = self.block_timeout.as_millis();
+        if timeout == 0 || timeout > u128::from(u32::MAX) {
+            return Err(Error::InvalidRingConfig("block timeout out of range"));
+        }
+        Ok(libc::tpacket_req3 {
+            tp_block_size: self.block_size,
+            tp_block_nr: self.block_count,
+            tp_frame_size: self.frame_size,
+            tp_frame_nr: self.block_size / self.frame_size * self.block_count,
+            tp_retire_blk_tov: timeout as u32,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        })
+    }
+}
+
+/// Kernel counters since the last `RxRing::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RingStats {
+    /// Frames that passed the filter, dropped ones included
+    pub packets: u32,
+    /// Frames lost because no block was free
+    pub drops: u32,
+    /// Times the ring filled up
+    pub freeze_queue_count: u32,
+}
+
+/// A raw socket receiving into a TPACKET_V3 ring, `recv_frame` on the
+/// socket won't see anything once the ring is set up
+pub struct RxRing {
+    socket: RawSocket,
+    map: *mut u8,
+    block_size: usize,
+    block_count: usize,
+    next: usize,
+}
+
+// The mapping belongs to the ring alone, handing it to another thread is fine
+unsafe impl Send for RxRing {}
+
+fn set_option<T>(
+    socket: &RawSocket,
+    action: &'static str,
+    name: libc::c_int,
+    value: &T,
+) -> Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_PACKET,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(Error::os(action));
+    }
+    Ok(())
+}
+
+impl RxRing {
+    pub(crate) fn new(socket: RawSocket, config: &RingConfig) -> Result<Self> {
+        let request = config.request()?;
+        let version = TPACKET_V3;
+        set_option(
+            &socket,
+            "selecting TPACKET_V3",
+            libc::PACKET_VERSION,
+            &version,
+        )?;
+        set_option(
+            &socket,
+            "setting up receive ring",
+            libc::PACKET_RX_RING,
+            &request,
+        )?;
+        let block_size = request.tp_block_size as usize;
+        let block_count = request.tp_block_nr as usize;
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                block_size * block_count,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                socket.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(Error::os("mapping receive ring"));
+        }
+        Ok(RxRing {
+            socket,
+            map: map as *mut u8,
+            block_size,
+            block_count,
+            next: 0,
+        })
+    }
+
+    /// The socket underneath, for sending and such
+    pub fn socket(&self) -> &RawSocket {
+        &self.socket
+    }
+
+    fn block_status(&self) -> &AtomicU32 {
+        let desc =
+            unsafe { self.map.add(self.next * self.block_size) } as *mut libc::tpacket_block_desc;
+        // The kernel writes the status from the other side of the mapping
+        unsafe { &*(ptr::addr_of_mut!((*desc).hdr.bh1.block_status) as *const AtomicU32) }
+    }
+
+    /// Blocks until the kernel hands over the next block of frames, which
+    /// goes back to the kernel when dropped
+    pub fn next_block(&mut self) -> Result<Block<'_>> {
+        while self.block_status().load(Ordering::Acquire) & libc::TP_STATUS_USER == 0 {
+            let mut fd = libc::pollfd {
+                fd: self.socket.as_raw_fd(),
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut fd, 1, -1) } == -1 {
+                let source = io::Error::last_os_error();
+                if source.kind() != io::ErrorKind::Interrupted {
+                    return Err(Error::Os {
+                        action: "waiting for a block",
+                        source,
+                    });
+                }
+            }
+        }
+        let desc = unsafe { self.map.add(self.next * self.block_size) };
+        Ok(Block { ring: self, desc })
+    }
+
+    /// Reading the counters resets them
+    pub fn stats(&self) -> Result<RingStats> {
+        let mut stats: libc::tpacket_stats_v3 = unsafe { mem::zeroed() };
+        let mut len = mem::size_of_val(&stats) as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                self.socket.as_raw_fd(),
+                libc::SOL_PACKET,
+                libc::PACKET_STATISTICS,
+                &mut stats as *mut libc::tpacket_stats_v3 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if res == -1 {
+            return Err(Error::os("getting ring statistics"));
+        }
+        Ok(RingStats {
+            packets: stats.tp_packets,
+            drops: stats.tp_drops,
+            freeze_queue_count: stats.tp_freeze_q_cnt,
+        })
+    }
+}
+
+impl Drop for RxRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(
+                self.map as *mut libc::c_void,
+                self.block_size * self.block_count,
+            )
+        };
+    }
+}
+
+/// A block handed over by the kernel
+pub struct Block<'a> {
+    ring: &'a mut RxRing,
+    desc: *mut u8,
+}
+
+impl<'a> Block<'a> {
+    fn header(&self) -> &libc::tpacket_hdr_v1 {
+        unsafe { &(*(self.desc as *const libc::tpacket_block_desc)).hdr.bh1 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.header().num_pkts as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Increments with every block the kernel fills, a gap means the
+    /// kernel went round the ring without us
+    pub fn sequence(&self) -> u64 {
+        self.header().seq_num
+    }
+
+    /// Handed over by the block timeout rather than for being full
+    pub fn timed_out(&self) -> bool {
+        self.header().block_status & libc::TP_STATUS_BLK_TMO != 0
+    }
+
+    pub fn frames(&self) -> Frames<'_> {
+        Frames {
+            desc: self.desc,
+            offset: self.header().offset_to_first_pkt as usize,
+            remaining: self.len(),
+            _block: self,
+        }
+    }
+}
+
+impl<'a> Drop for Block<'a> {
+    fn drop(&mut self) {
+        let ring = &mut *self.ring;
+        ring.block_status()
+            .store(libc::TP_STATUS_KERNEL, Ordering::Release);
+        ring.next = (ring.next + 1) % ring.block_count;
+    }
+}
+
+/// A frame as the kernel stored it in the ring
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame<'a> {
+    /// The frame from the Ethernet header on, without a VLAN tag the NIC
+    /// took off
+    pub data: &'a [u8],
+    /// Length on the wire, more than `data` holds if it was cut short
+    pub len: usize,
+    /// When the kernel received the frame
+    pub timestamp: SystemTime,
+    pub vlan_tci: Option<u16>,
+}
+
+pub struct Frames<'a> {
+    _block: &'a Block<'a>,
+    desc: *mut u8,
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Frame<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let frame = unsafe { self.desc.add(self.offset) };
+        let header = unsafe { &*(frame as *const libc::tpacket3_hdr) };
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                frame.add(usize::from(header.tp_mac)),
+                header.tp_snaplen as usize,
+            )
+        };
+        self.offset += header.tp_next_offset as usize;
+        let vlan_tci = if header.tp_status & libc::TP_STATUS_VLAN_VALID != 0 {
+            Some(header.hv1.tp_vlan_tci as u16)
+        } else {
+            None
+        };
+        Some(Frame {
+            data,
+            len: header.tp_len as usize,
+            timestamp: SystemTime::UNIX_EPOCH
+                + Duration::new(u64::from(header.tp_sec), header.tp_nsec),
+            vlan_tci,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_config() {
+        let request = RingConfig::default().request().unwrap();
+        assert_eq!(request.tp_frame_nr, 64 * 8);
+        assert_eq!(request.tp_retire_blk_tov, 100);
+        let invalid = [
+            RingConfig {
+                block_size: 5000,
+                ..RingConfig::default()
+            },
+            RingConfig {
+                block_count: 0,
+                ..RingConfig::default()
+            },
+            RingConfig {
+                frame_size: 1000,
+                ..RingConfig::default()
+            },
+            RingConfig {
+                frame_size: 1 << 18,
+                ..RingConfig::default()
+            },
+            RingConfig {
+                block_timeout: Duration::from_micros(10),
+                ..RingConfig::default()
+            },
+        ];
+        for config in invalid {
+            assert!(
+                matches!(config.request(), Err(Error::InvalidRingConfig(_))),
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn receives_on_lo() {
+        let ether_type = 0x88bc;
+        let socket = match RawSocket::open("lo", ether_type) {
+            Ok(socket) => socket,
+            Err(Error::Os { source, .. }) if source.kind() == io::ErrorKind::PermissionDenied => {
+                return
+            }
+            Err(err) => panic!("opening lo: {}", err),
+        };
+        let config = RingConfig {
+            block_count: 2,
+            block_timeout: Duration::from_millis(10),
+            ..RingConfig::default()
+        };
+        let mut ring = socket.into_rx_ring(&config).unwrap();
+        let mut sequences = Vec::new();
+        // Both blocks go round twice, so each has to be given back on drop
+        for seq in 0..4 {
+            let mut frame = [0u8; 60];
+            frame[6] = 0x02;
+            frame[12..14].copy_from_slice(&ether_type.to_be_bytes());
+            frame[14] = seq;
+            ring.socket().send_frame(&frame).unwrap();
+
+            let block = ring.next_block().unwrap();
+            assert!(block.timed_out());
+            let frames: Vec<Frame> = block.frames().collect();
+            assert_eq!(frames.len(), block.len());
+            assert_eq!(frames.len(), 1);
+            assert_eq!(frames[0].data, &frame[..]);
+            assert_eq!(frames[0].len, 60);
+            assert_eq!(frames[0].vlan_tci, None);
+            sequences.push(block.sequence());
+        }
+        assert!(sequences.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert_eq!(ring.stats().unwrap().packets, 4);
+    }
+}
@@ -0,0 +1,599 @@
+//! EVSE side of the SLAC matching process
+
+use std::time::{Duration, Instant};
+
+use super::profile::{AttenuationReport, ProfileAggregator};
+use super::validate::BcbToggle;
+use super::{
+    check_run_id, SlacError, Transmit, C_EV_MATCH_MNBC, C_EV_MATCH_RETRY, TT_EVSE_MATCH_MNBC,
+    TT_EVSE_MATCH_SESSION, TT_MATCH_RESPONSE,
+};
+use crate::messages::{
+    AttenCharInd, MacAddr, Message, RunId, SlacMatchCnf, SlacParmCnf, StationId, ValidateCnf,
+    ATTEN_CHAR_RESULT_SUCCESS, VALIDATE_RESULT_FAILURE, VALIDATE_RESULT_NOT_READY,
+    VALIDATE_RESULT_NOT_REQUIRED, VALIDATE_RESULT_READY, VALIDATE_RESULT_SUCCESS,
+};
+
+pub struct EvseConfig {
+    pub evse_id: StationId,
+    /// MAC address of the local PLC modem, destination of CM_SET_KEY.REQ
+    pub modem_mac: MacAddr,
+    /// Network the PEV is asked to join
+    pub nid: [u8; 7],
+    pub nmk: [u8; 16],
+    pub num_sounds: u8,
+    /// Sounding window, in multiples of 100 ms
+    pub time_out: u8,
+}
+
+impl EvseConfig {
+    pub fn new(modem_mac: MacAddr, nid: [u8; 7], nmk: [u8; 16]) -> Self {
+        EvseConfig {
+            evse_id: [0; 17],
+            modem_mac,
+            nid,
+            nmk,
+            num_sounds: C_EV_MATCH_MNBC,
+            time_out: TT_EVSE_MATCH_MNBC,
+        }
+    }
+}
+
+/// Result of a successful matching, everything needed to move on to SDP
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchOutcome {
+    pub pev_mac: MacAddr,
+    pub nid: [u8; 7],
+    pub nmk: [u8; 16],
+    pub run_id: RunId,
+    /// Coupling quality the match was decided on
+    pub attenuation: AttenuationReport,
+}
+
+#[derive(Debug)]
+enum State {
+    WaitSlacParm,
+    WaitStartAttenChar,
+    Sounding {
+        deadline: Instant,
+        sounds: u8,
+        // Cnt of the last sound, it counts down towards zero
+        last_cnt: Option<u8>,
+        profiles: Box<ProfileAggregator>,
+    },
+    WaitAttenCharRsp {
+        deadline: Instant,
+        retries: u8,
+        ind: Box<AttenCharInd>,
+        report: AttenuationReport,
+    },
+    WaitSlacMatch {
+        deadline: Instant,
+        report: AttenuationReport,
+    },
+    Validating {
+        deadline: Instant,
+        report: AttenuationReport,
+    },
+    Matched(MatchOutcome),
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::WaitSlacParm => "waiting for CM_SLAC_PARM.REQ",
+            State::WaitStartAttenChar => "waiting for CM_START_ATTEN_CHAR.IND",
+            State::Sounding { .. } => "collecting sounds",
+            State::WaitAttenCharRsp { .. } => "waiting for CM_ATTEN_CHAR.RSP",
+            State::WaitSlacMatch { .. } => "waiting for CM_SLAC_MATCH.REQ",
+            State::Validating { .. } => "counting BCB toggles",
+            State::Matched(_) => "matched",
+        }
+    }
+}
+
+struct Session {
+    pev_mac: MacAddr,
+    run_id: RunId,
+}
+
+pub struct Evse {
+    config: EvseConfig,
+    state: State,
+    session: Option<Session>,
+    bcb_toggle: Option<Box<dyn BcbToggle>>,
+}
+
+impl Evse {
+    pub fn new(config: EvseConfig) -> Self {
+        Evse {
+            config,
+            state: State::WaitSlacParm,
+            session: None,
+            bcb_toggle: None,
+        }
+    }
+
+    /// Takes part in CM_VALIDATE, without it the PEV is told validation is
+    /// not required
+    pub fn with_bcb_toggle(mut self, bcb_toggle: Box<dyn BcbToggle>) -> Self {
+        self.bcb_toggle = Some(bcb_toggle);
+        self
+    }
+
+    /// The match, once CM_SLAC_MATCH.CNF and CM_SET_KEY.REQ went out
+    pub fn outcome(&self) -> Option<&MatchOutcome> {
+        match &self.state {
+            State::Matched(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    /// Attenuation profile of the current session, once the sounding is over
+    pub fn attenuation(&self) -> Option<&AttenuationReport> {
+        match &self.state {
+            State::WaitAttenCharRsp { report, .. }
+            | State::WaitSlacMatch { report, .. }
+            | State::Validating { report, .. } => Some(report),
+            State::Matched(outcome) => Some(&outcome.attenuation),
+            _ => None,
+        }
+    }
+
+    /// Instant at which `handle_timeout` has to be called
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::Sounding { deadline, .. }
+            | State::WaitAttenCharRsp { deadline, .. }
+            | State::WaitSlacMatch { deadline, .. }
+            | State::Validating { deadline, .. } => Some(deadline),
+            _ => None,
+        }
+    }
+
+    /// Drops the current session and waits for a new CM_SLAC_PARM.REQ
+    pub fn reset(&mut self) {
+        self.state = State::WaitSlacParm;
+        self.session = None;
+    }
+
+    pub fn handle(
+        &mut self,
+        source: MacAddr,
+        message: Message,
+        now: Instant,
+    ) -> Result<Vec<Transmit>, SlacError> {
+        match (&mut self.state, message) {
+            // The PEV repeats CM_SLAC_PARM.REQ when our confirmation got lost
+            (State::WaitSlacParm, Message::SlacParmReq(req))
+            | (State::WaitStartAttenChar, Message::SlacParmReq(req)) => {
+                self.session = Some(Session {
+                    pev_mac: source,
+                    run_id: req.run_id,
+                });
+                self.state = State::WaitStartAttenChar;
+                let cnf = SlacParmCnf::new(
+                    source,
+                    self.config.num_sounds,
+                    self.config.time_out,
+                    req.run_id,
+                );
+                Ok(vec![Transmit {
+                    destination: source,
+                    message: Message::SlacParmCnf(cnf),
+                }])
+            }
+            (State::WaitStartAttenChar, Message::StartAttenCharInd(ind)) => {
+                self.check_session(source, ind.run_id)?;
+                if ind.forwarding_sta != source {
+                    return Err(SlacError::UnexpectedSender {
+                        expected: source,
+                        received: ind.forwarding_sta,
+                    });
+                }
+                let window = Duration::from_millis(100 * u64::from(self.config.time_out));
+                self.state = State::Sounding {
+                    deadline: now + window,
+                    sounds: 0,
+                    last_cnt: None,
+                    profiles: Box::new(ProfileAggregator::new()),
+                };
+                Ok(vec![])
+            }
+            // CM_START_ATTEN_CHAR.IND is sent several times by the PEV
+            (State::Sounding { .. }, Message::StartAttenCharInd(ind)) => {
+                self.check_session(source, ind.run_id)?;
+                Ok(vec![])
+            }
+            (
+                State::Sounding {
+                    sounds, last_cnt, ..
+                },
+                Message::MnbcSoundInd(ind),
+            ) => {
+                let session = self.session.as_ref().expect("sounding without a session");
+                check_run_id(session.run_id, ind.run_id)?;
+                if session.pev_mac != source {
+                    return Err(SlacError::UnexpectedSender {
+                        expected: session.pev_mac,
+                        received: source,
+                    });
+                }
+                // Repeated or out of order sounds are not counted twice
+                if last_cnt.map_or(true, |last| ind.cnt < last) {
+                    *last_cnt = Some(ind.cnt);
+                    *sounds = sounds.saturating_add(1);
+                }
+                Ok(vec![])
+            }
+            // Reported by the local modem, so the PEV is identified by the payload
+            (State::Sounding { profiles, .. }, Message::AttenProfileInd(ind)) => {
+                let session = self.session.as_ref().expect("sounding without a session");
+                if session.pev_mac != ind.pev_mac {
+                    return Err(SlacError::UnexpectedSender {
+                        expected: session.pev_mac,
+                        received: ind.pev_mac,
+                    });
+                }
+                profiles.add(&ind.aag);
+                if profiles.count() >= self.config.num_sounds {
+                    self.finish_sounding(now)
+                } else {
+                    Ok(vec![])
+                }
+            }
+            (State::WaitAttenCharRsp { report, .. }, Message::AttenCharRsp(rsp)) => {
+                let report = report.clone();
+                self.check_session(source, rsp.run_id)?;
+                if rsp.result != ATTEN_CHAR_RESULT_SUCCESS {
+                    self.reset();
+                    return Err(SlacError::Refused {
+                        message: "CM_ATTEN_CHAR.RSP",
+                    });
+                }
+                self.state = State::WaitSlacMatch {
+                    deadline: now + TT_EVSE_MATCH_SESSION,
+                    report,
+                };
+                Ok(vec![])
+            }
+            (State::WaitSlacMatch { report, .. }, Message::ValidateReq(req)) => {
+                let report = report.clone();
+                self.check_pev(source)?;
+                let (toggle_num, result) = match self.bcb_toggle.as_mut() {
+                    None => (0, VALIDATE_RESULT_NOT_REQUIRED),
+                    Some(bcb) if req.timer == 0 => {
+                        let ready = bcb.ready();
+                        let result = if ready {
+                            VALIDATE_RESULT_READY
+                        } else {
+                            VALIDATE_RESULT_NOT_READY
+                        };
+                        (0, result)
+                    }
+                    // The confirmation goes out once the toggle window is over
+                    Some(bcb) => {
+                        let window = Duration::from_millis(100 * u64::from(req.timer));
+                        bcb.start(window);
+                        self.state = State::Validating {
+                            deadline: now + window,
+                            report,
+                        };
+                        return Ok(vec![]);
+                    }
+                };
+                Ok(vec![Transmit {
+                    destination: source,
+                    message: Message::ValidateCnf(ValidateCnf::new(toggle_num, result)),
+                }])
+            }
+            // A lost CM_ATTEN_CHAR.RSP doesn't keep the PEV from going on
+            (State::WaitAttenCharRsp { report, .. }, Message::SlacMatchReq(req))
+            | (State::WaitSlacMatch { report, .. }, Message::SlacMatchReq(req)) => {
+                let report = report.clone();
+                self.check_session(source, req.run_id)?;
+                if req.pev_mac != source {
+                    return Err(SlacError::UnexpectedSender {
+                        expected: source,
+                        received: req.pev_mac,
+                    });
+                }
+                let outcome = MatchOutcome {
+                    pev_mac: source,
+                    nid: self.config.nid,
+                    nmk: self.config.nmk,
+                    run_id: req.run_id,
+                    attenuation: report,
+                };
+                let cnf =
+                    SlacMatchCnf::new(&req, self.config.evse_id, self.config.nid, self.config.nmk);
+                let set_key = cnf.set_key_req();
+                self.state = State::Matched(outcome);
+                Ok(vec![
+                    Transmit {
+                        destination: source,
+                        message: Message::SlacMatchCnf(cnf),
+                    },
+                    Transmit {
+                        destination: self.config.modem_mac,
+                        message: Message::SetKeyReq(set_key),
+                    },
+                ])
+            }
+            (state, _) => Err(SlacError::UnexpectedMessage {
+                state: state.name(),
+            }),
+        }
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) -> Result<Vec<Transmit>, SlacError> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => (),
+            _ => return Ok(vec![]),
+        }
+        match &mut self.state {
+            State::Sounding { .. } => self.finish_sounding(now),
+            State::WaitAttenCharRsp {
+                deadline,
+                retries,
+                ind,
+                ..
+            } if *retries < C_EV_MATCH_RETRY => {
+                *deadline = now + TT_MATCH_RESPONSE;
+                *retries += 1;
+                Ok(vec![Transmit {
+                    destination: ind.source_address,
+                    message: Message::AttenCharInd((**ind).clone()),
+                }])
+            }
+            State::Validating { report, .. } => {
+                let report = report.clone();
+                let bcb = self
+                    .bcb_toggle
+                    .as_mut()
+                    .expect("validating without a BCB hook");
+                let toggle_num = bcb.toggles();
+                let result = if toggle_num > 0 {
+                    VALIDATE_RESULT_SUCCESS
+                } else {
+                    VALIDATE_RESULT_FAILURE
+                };
+                self.state = State::WaitSlacMatch {
+                    deadline: now + TT_EVSE_MATCH_SESSION,
+                    report,
+                };
+                let session = self.session.as_ref().expect("validating without a session");
+                Ok(vec![Transmit {
+                    destination: session.pev_mac,
+                    message: Message::ValidateCnf(ValidateCnf::new(toggle_num, result)),
+                }])
+            }
+            state => {
+                let state = state.name();
+                self.reset();
+                Err(SlacError::Timeout { state })
+            }
+        }
+    }
+
+    /// Sends the averaged attenuation profile back to the PEV
+    fn finish_sounding(&mut self, now: Instant) -> Result<Vec<Transmit>, SlacError> {
+        let (sounds, report) = match &self.state {
+            State::Sounding {
+                sounds, profiles, ..
+            } => (*sounds, AttenuationReport::new(profiles)),
+            _ => unreachable!(),
+        };
+        let report = match report {
+            Some(report) => report,
+            None => {
+                let state = self.state.name();
+                self.reset();
+                return Err(SlacError::Timeout { state });
+            }
+        };
+        let session = self.session.as_ref().expect("sounding without a session");
+        let ind = AttenCharInd::new(
+            session.pev_mac,
+            session.run_id,
+            self.config.evse_id,
+            sounds.max(report.num_sounds),
+            report.profile,
+        );
+        let transmit = Transmit {
+            destination: session.pev_mac,
+            message: Message::AttenCharInd(ind.clone()),
+        };
+        self.state = State::WaitAttenCharRsp {
+            deadline: now + TT_MATCH_RESPONSE,
+            retries: 0,
+            ind: Box::new(ind),
+            report,
+        };
+        Ok(vec![transmit])
+    }
+
+    fn check_pev(&self, source: MacAddr) -> Result<(), SlacError> {
+        let session = self.session.as_ref().expect("no active session");
+        if session.pev_mac != source {
+            return Err(SlacError::UnexpectedSender {
+                expected: session.pev_mac,
+                received: source,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_session(&self, source: MacAddr, run_id: RunId) -> Result<(), SlacError> {
+        self.check_pev(source)?;
+        let session = self.session.as_ref().expect("no active session");
+        check_run_id(session.run_id, run_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{
+        AttenCharRsp, AttenProfileInd, AttenuationGroups, MnbcSoundInd, SlacMatchReq, SlacParmReq,
+        StartAttenCharInd, ValidateReq, ATTEN_GROUPS,
+    };
+
+    const PEV_MAC: MacAddr = [0x02, 0, 0, 0, 0, 0x01];
+    const EVSE_MAC: MacAddr = [0x02, 0, 0, 0, 0, 0x02];
+    const MODEM_MAC: MacAddr = [0x00, 0xb0, 0x52, 0, 0, 0x02];
+    const RUN_ID: RunId = [1; 8];
+    const NID: [u8; 7] = [0x33; 7];
+    const NMK: [u8; 16] = [0x44; 16];
+
+    fn start(evse: &mut Evse, now: Instant) {
+        let req = Message::SlacParmReq(SlacParmReq::new(RUN_ID));
+        evse.handle(PEV_MAC, req, now).unwrap();
+        let start = Message::StartAttenCharInd(StartAttenCharInd::new(
+            PEV_MAC,
+            C_EV_MATCH_MNBC,
+            TT_EVSE_MATCH_MNBC,
+            RUN_ID,
+        ));
+        evse.handle(PEV_MAC, start, now).unwrap();
+    }
+
+    #[test]
+    fn matches_after_sounding() {
+        let mut evse = Evse::new(EvseConfig::new(MODEM_MAC, NID, NMK));
+        let now = Instant::now();
+        start(&mut evse, now);
+        let mut ind = Vec::new();
+        for sound in MnbcSoundInd::burst(C_EV_MATCH_MNBC, RUN_ID, [0; 17]) {
+            let cnt = sound.cnt;
+            evse.handle(PEV_MAC, Message::MnbcSoundInd(sound.clone()), now)
+                .unwrap();
+            // A repeated sound is not counted twice
+            evse.handle(PEV_MAC, Message::MnbcSoundInd(sound), now)
+                .unwrap();
+            // Alternating 20 and 21 dB, averaged to 20.5 and rounded up
+            let profile = Message::AttenProfileInd(AttenProfileInd {
+                pev_mac: PEV_MAC,
+                num_groups: ATTEN_GROUPS as u8,
+                rsvd: 0,
+                aag: AttenuationGroups([20 + cnt % 2; ATTEN_GROUPS]),
+            });
+            ind = evse.handle(MODEM_MAC, profile, now).unwrap();
+        }
+        let expected = AttenCharInd::new(
+            PEV_MAC,
+            RUN_ID,
+            [0; 17],
+            C_EV_MATCH_MNBC,
+            AttenuationGroups([21; ATTEN_GROUPS]),
+        );
+        assert_eq!(
+            ind,
+            vec![Transmit {
+                destination: PEV_MAC,
+                message: Message::AttenCharInd(expected.clone()),
+            }]
+        );
+        assert_eq!(evse.attenuation().unwrap().mean_attenuation, 21.0);
+
+        // The indication is repeated until the PEV acknowledges it
+        let retry = evse.handle_timeout(now + TT_MATCH_RESPONSE).unwrap();
+        assert_eq!(retry, ind);
+        let rsp = AttenCharRsp::new(&expected, ATTEN_CHAR_RESULT_SUCCESS);
+        evse.handle(PEV_MAC, Message::AttenCharRsp(rsp), now)
+            .unwrap();
+
+        let req = Message::SlacMatchReq(SlacMatchReq::new(PEV_MAC, EVSE_MAC, RUN_ID));
+        let matched = evse.handle(PEV_MAC, req, now).unwrap();
+        assert_eq!(matched[0].destination, PEV_MAC);
+        assert_eq!(matched[1].destination, MODEM_MAC);
+        match &matched[1].message {
+            Message::SetKeyReq(req) => assert_eq!((req.nid(), req.new_key()), (NID, NMK)),
+            message => panic!("{:?} instead of CM_SET_KEY.REQ", message),
+        }
+        let outcome = evse.outcome().unwrap();
+        assert_eq!((outcome.pev_mac, outcome.run_id), (PEV_MAC, RUN_ID));
+        assert_eq!((outcome.nid, outcome.nmk), (NID, NMK));
+    }
+
+    struct Toggles(u8);
+
+    impl BcbToggle for Toggles {
+        fn ready(&mut self) -> bool {
+            true
+        }
+
+        fn start(&mut self, _window: Duration) {}
+
+        fn toggles(&mut self) -> u8 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn validates_with_bcb_toggles() {
+        let mut evse =
+            Evse::new(EvseConfig::new(MODEM_MAC, NID, NMK)).with_bcb_toggle(Box::new(Toggles(3)));
+        let now = Instant::now();
+        start(&mut evse, now);
+        for _ in 0..C_EV_MATCH_MNBC {
+            let profile = Message::AttenProfileInd(AttenProfileInd {
+                pev_mac: PEV_MAC,
+                num_groups: ATTEN_GROUPS as u8,
+                rsvd: 0,
+                aag: AttenuationGroups([20; ATTEN_GROUPS]),
+            });
+            evse.handle(MODEM_MAC, profile, now).unwrap();
+        }
+        let ind = AttenCharInd::new(
+            PEV_MAC,
+            RUN_ID,
+            [0; 17],
+            C_EV_MATCH_MNBC,
+            AttenuationGroups([20; ATTEN_GROUPS]),
+        );
+        let rsp = AttenCharRsp::new(&ind, ATTEN_CHAR_RESULT_SUCCESS);
+        evse.handle(PEV_MAC, Message::AttenCharRsp(rsp), now)
+            .unwrap();
+
+        let ready = evse
+            .handle(PEV_MAC, Message::ValidateReq(ValidateReq::new(0)), now)
+            .unwrap();
+        assert_eq!(
+            ready[0].message,
+            Message::ValidateCnf(ValidateCnf::new(0, VALIDATE_RESULT_READY))
+        );
+        // The toggle count is only confirmed once the 2 s window is over
+        let started = evse
+            .handle(PEV_MAC, Message::ValidateReq(ValidateReq::new(20)), now)
+            .unwrap();
+        assert!(started.is_empty());
+        assert_eq!(evse.deadline(), Some(now + Duration::from_secs(2)));
+        let cnf = evse.handle_timeout(now + Duration::from_secs(2)).unwrap();
+        assert_eq!(
+            cnf,
+            vec![Transmit {
+                destination: PEV_MAC,
+                message: Message::ValidateCnf(ValidateCnf::new(3, VALIDATE_RESULT_SUCCESS)),
+            }]
+        );
+        let req = Message::SlacMatchReq(SlacMatchReq::new(PEV_MAC, EVSE_MAC, RUN_ID));
+        evse.handle(PEV_MAC, req, now).unwrap();
+        assert!(evse.outcome().is_some());
+    }
+
+    #[test]
+    fn rejects_foreign_run_id() {
+        let mut evse = Evse::new(EvseConfig::new(MODEM_MAC, NID, NMK));
+        let now = Instant::now();
+        start(&mut evse, now);
+        let sound = Message::MnbcSoundInd(MnbcSoundInd::new(9, [2; 8], [0; 16]));
+        assert_eq!(
+            evse.handle(PEV_MAC, sound, now),
+            Err(SlacError::RunIdMismatch {
+                expected: RUN_ID,
+                received: [2; 8]
+            })
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! ISO 15118-3 SLAC (Signal Level Attenuation Characterization) matching.
+//!
+//! The state machines in here don't do any I/O: they are fed with the messages
+//! received from the wire and return the messages that have to be sent back.
+//! `set_key::set_key` is the one blocking helper on top of them.
+
+pub mod evse;
+pub mod pev;
+pub mod profile;
+pub mod set_key;
+pub mod validate;
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::messages::{MacAddr, Message, RunId};
+
+/// C_EV_match_MNBC, number of sounds the PEV sends
+pub const C_EV_MATCH_MNBC: u8 = 10;
+/// TT_EVSE_match_MNBC, time the EVSE waits for the sounds, in multiples of 100 ms
+pub const TT_EVSE_MATCH_MNBC: u8 = 6;
+/// TT_EVSE_match_session, time the EVSE waits for CM_SLAC_MATCH.REQ
+pub const TT_EVSE_MATCH_SESSION: Duration = Duration::from_secs(10);
+/// TT_match_sequence, time the PEV waits for CM_SLAC_PARM.CNF
+pub const TT_MATCH_SEQUENCE: Duration = Duration::from_millis(400);
+/// TT_match_response, time the PEV waits for CM_SLAC_MATCH.CNF
+pub const TT_MATCH_RESPONSE: Duration = Duration::from_millis(200);
+/// TT_EV_atten_results, time the PEV collects CM_ATTEN_CHAR.IND
+pub const TT_EV_ATTEN_RESULTS: Duration = Duration::from_millis(1200);
+/// C_EV_match_retry, how many times the PEV repeats an unanswered request
+pub const C_EV_MATCH_RETRY: u8 = 2;
+/// C_EV_start_atten_char_inds, repetitions of CM_START_ATTEN_CHAR.IND
+pub const C_EV_START_ATTEN_CHAR_INDS: u8 = 3;
+/// TP_EV_batch_msg_interval, spacing of the CM_START_ATTEN_CHAR.IND and
+/// CM_MNBC_SOUND.IND frames, between 20 and 50 ms
+pub const TP_EV_BATCH_MSG_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A message the state machine wants to put on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transmit {
+    pub destination: MacAddr,
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SlacError {
+    UnexpectedMessage {
+        state: &'static str,
+    },
+    UnexpectedSender {
+        expected: MacAddr,
+        received: MacAddr,
+    },
+    RunIdMismatch {
+        expected: RunId,
+        received: RunId,
+    },
+    /// The peer answered `message` with a failure result
+    Refused {
+        message: &'static str,
+    },
+    Timeout {
+        state: &'static str,
+    },
+    /// The confirmation doesn't echo the nonce of the request
+    NonceMismatch {
+        expected: [u8; 4],
+        received: [u8; 4],
+    },
+}
+
+impl fmt::Display for SlacError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SlacError::UnexpectedMessage { state } => {
+                write!(f, "unexpected message while {}", state)
+            }
+            SlacError::UnexpectedSender { expected, received } => write!(
+                f,
+                "message from {:02x?} while in session with {:02x?}",
+                received, expected
+            ),
+            SlacError::RunIdMismatch { expected, received } => write!(
+                f,
+                "run id {:02x?} doesn't match the session run id {:02x?}",
+                received, expected
+            ),
+            SlacError::Refused { message } => write!(f, "{} reported a failure", message),
+            SlacError::Timeout { state } => write!(f, "timed out while {}", state),
+            SlacError::NonceMismatch { expected, received } => write!(
+                f,
+                "nonce {:02x?} doesn't match the request nonce {:02x?}",
+                received, expected
+            ),
+        }
+    }
+}
+
+impl Error for SlacError {}
+
+fn check_run_id(expected: RunId, received: RunId) -> Result<(), SlacError> {
+    if expected != received {
+        return Err(SlacError::RunIdMismatch { expected, received });
+    }
+    Ok(())
+}
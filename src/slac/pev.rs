@@ -0,0 +1,376 @@
+//! PEV side of the SLAC matching process
+
+use std::time::Instant;
+
+use super::{
+    check_run_id, SlacError, Transmit, C_EV_MATCH_RETRY, C_EV_START_ATTEN_CHAR_INDS,
+    TT_EV_ATTEN_RESULTS, TT_MATCH_RESPONSE, TT_MATCH_SEQUENCE,
+};
+use crate::messages::{
+    AttenCharInd, AttenCharRsp, AttenuationGroups, MacAddr, Message, MnbcSoundInd, RunId,
+    SlacMatchReq, SlacParmCnf, SlacParmReq, StartAttenCharInd, StationId,
+    ATTEN_CHAR_RESULT_SUCCESS, BROADCAST_MAC,
+};
+
+pub struct PevConfig {
+    /// MAC address of the PEV host, the STA the EVSEs report their results to
+    pub pev_mac: MacAddr,
+    pub pev_id: StationId,
+    /// MAC address of the local PLC modem, destination of CM_SET_KEY.REQ
+    pub modem_mac: MacAddr,
+}
+
+impl PevConfig {
+    pub fn new(pev_mac: MacAddr, modem_mac: MacAddr) -> Self {
+        PevConfig {
+            pev_mac,
+            pev_id: [0; 17],
+            modem_mac,
+        }
+    }
+}
+
+/// An EVSE that answered the sounding with CM_ATTEN_CHAR.IND
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub evse_mac: MacAddr,
+    pub evse_id: StationId,
+    /// Number of sounds the EVSE actually received
+    pub num_sounds: u8,
+    pub aag: AttenuationGroups,
+    /// Mean of the attenuation groups, in dB
+    pub average_attenuation: f32,
+}
+
+impl Candidate {
+    fn from_ind(evse_mac: MacAddr, ind: &AttenCharInd) -> Self {
+        Candidate {
+            evse_mac,
+            evse_id: ind.resp_id,
+            num_sounds: ind.num_sounds,
+            aag: ind.aag,
+            average_attenuation: ind.aag.mean(),
+        }
+    }
+}
+
+/// Why an EVSE was chosen: the lowest average attenuation among all the
+/// candidates that answered within TT_EV_atten_results
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub chosen: Candidate,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Selection {
+    fn choose(candidates: Vec<Candidate>) -> Option<Self> {
+        let chosen = candidates
+            .iter()
+            .min_by(|a, b| {
+                a.average_attenuation
+                    .partial_cmp(&b.average_attenuation)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })?
+            .clone();
+        Some(Selection { chosen, candidates })
+    }
+}
+
+/// Result of a successful matching, the PEV is now part of the EVSE network
+#[derive(Debug, Clone, PartialEq)]
+pub struct PevMatch {
+    pub evse_mac: MacAddr,
+    pub nid: [u8; 7],
+    pub nmk: [u8; 16],
+    pub run_id: RunId,
+    pub selection: Selection,
+}
+
+#[derive(Debug)]
+enum State {
+    Idle,
+    WaitSlacParmCnf {
+        deadline: Instant,
+        retries: u8,
+    },
+    WaitAttenChar {
+        deadline: Instant,
+        candidates: Vec<Candidate>,
+    },
+    WaitSlacMatchCnf {
+        deadline: Instant,
+        retries: u8,
+        selection: Selection,
+    },
+    Matched(PevMatch),
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::WaitSlacParmCnf { .. } => "waiting for CM_SLAC_PARM.CNF",
+            State::WaitAttenChar { .. } => "waiting for CM_ATTEN_CHAR.IND",
+            State::WaitSlacMatchCnf { .. } => "waiting for CM_SLAC_MATCH.CNF",
+            State::Matched(_) => "matched",
+        }
+    }
+}
+
+pub struct Pev {
+    config: PevConfig,
+    state: State,
+    run_id: RunId,
+}
+
+impl Pev {
+    pub fn new(config: PevConfig) -> Self {
+        Pev {
+            config,
+            state: State::Idle,
+            run_id: [0; 8],
+        }
+    }
+
+    pub fn outcome(&self) -> Option<&PevMatch> {
+        match &self.state {
+            State::Matched(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    /// Instant at which `handle_timeout` has to be called
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::WaitSlacParmCnf { deadline, .. }
+            | State::WaitAttenChar { deadline, .. }
+            | State::WaitSlacMatchCnf { deadline, .. } => Some(deadline),
+            _ => None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Starts a new session with a fresh run id
+    pub fn start(&mut self, now: Instant) -> Vec<Transmit> {
+        self.run_id = rand::random();
+        self.state = State::WaitSlacParmCnf {
+            deadline: now + TT_MATCH_SEQUENCE,
+            retries: 0,
+        };
+        vec![self.slac_parm_req()]
+    }
+
+    pub fn handle(
+        &mut self,
+        source: MacAddr,
+        message: Message,
+        now: Instant,
+    ) -> Result<Vec<Transmit>, SlacError> {
+        match (&mut self.state, message) {
+            (State::WaitSlacParmCnf { .. }, Message::SlacParmCnf(cnf)) => {
+                check_run_id(self.run_id, cnf.run_id)?;
+                self.state = State::WaitAttenChar {
+                    deadline: now + TT_EV_ATTEN_RESULTS,
+                    candidates: Vec::new(),
+                };
+                Ok(self.sounding(&cnf))
+            }
+            // Every EVSE sharing the cable bundle answers, only the first one
+            // sets the sounding parameters
+            (State::WaitAttenChar { .. }, Message::SlacParmCnf(cnf)) => {
+                check_run_id(self.run_id, cnf.run_id)?;
+                Ok(vec![])
+            }
+            (State::WaitAttenChar { candidates, .. }, Message::AttenCharInd(ind)) => {
+                check_run_id(self.run_id, ind.run_id)?;
+                if ind.source_address != self.config.pev_mac {
+                    return Err(SlacError::UnexpectedSender {
+                        expected: self.config.pev_mac,
+                        received: ind.source_address,
+                    });
+                }
+                candidates.retain(|c| c.evse_mac != source);
+                candidates.push(Candidate::from_ind(source, &ind));
+                let rsp = AttenCharRsp::new(&ind, ATTEN_CHAR_RESULT_SUCCESS);
+                Ok(vec![Transmit {
+                    destination: source,
+                    message: Message::AttenCharRsp(rsp),
+                }])
+            }
+            (State::WaitSlacMatchCnf { selection, .. }, Message::SlacMatchCnf(cnf)) => {
+                check_run_id(self.run_id, cnf.run_id)?;
+                if selection.chosen.evse_mac != source {
+                    return Err(SlacError::UnexpectedSender {
+                        expected: selection.chosen.evse_mac,
+                        received: source,
+                    });
+                }
+                let outcome = PevMatch {
+                    evse_mac: source,
+                    nid: cnf.nid,
+                    nmk: cnf.nmk,
+                    run_id: cnf.run_id,
+                    selection: selection.clone(),
+                };
+                self.state = State::Matched(outcome);
+                Ok(vec![Transmit {
+                    destination: self.config.modem_mac,
+                    message: Message::SetKeyReq(cnf.set_key_req()),
+                }])
+            }
+            (state, _) => Err(SlacError::UnexpectedMessage {
+                state: state.name(),
+            }),
+        }
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) -> Result<Vec<Transmit>, SlacError> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => (),
+            _ => return Ok(vec![]),
+        }
+        let state = std::mem::replace(&mut self.state, State::Idle);
+        match state {
+            State::WaitSlacParmCnf { retries, .. } if retries < C_EV_MATCH_RETRY => {
+                self.state = State::WaitSlacParmCnf {
+                    deadline: now + TT_MATCH_SEQUENCE,
+                    retries: retries + 1,
+                };
+                Ok(vec![self.slac_parm_req()])
+            }
+            State::WaitAttenChar { candidates, .. } if !candidates.is_empty() => {
+                let selection = Selection::choose(candidates).expect("no candidates");
+                let req = self.slac_match_req(&selection);
+                self.state = State::WaitSlacMatchCnf {
+                    deadline: now + TT_MATCH_RESPONSE,
+                    retries: 0,
+                    selection,
+                };
+                Ok(vec![req])
+            }
+            State::WaitSlacMatchCnf {
+                retries, selection, ..
+            } if retries < C_EV_MATCH_RETRY => {
+                let req = self.slac_match_req(&selection);
+                self.state = State::WaitSlacMatchCnf {
+                    deadline: now + TT_MATCH_RESPONSE,
+                    retries: retries + 1,
+                    selection,
+                };
+                Ok(vec![req])
+            }
+            state => Err(SlacError::Timeout {
+                state: state.name(),
+            }),
+        }
+    }
+
+    fn slac_parm_req(&self) -> Transmit {
+        Transmit {
+            destination: BROADCAST_MAC,
+            message: Message::SlacParmReq(SlacParmReq::new(self.run_id)),
+        }
+    }
+
+    /// CM_START_ATTEN_CHAR.IND repetitions followed by the M-Node-Broadcast sounds
+    fn sounding(&self, cnf: &SlacParmCnf) -> Vec<Transmit> {
+        let start = StartAttenCharInd::new(
+            self.config.pev_mac,
+            cnf.num_sounds,
+            cnf.time_out,
+            self.run_id,
+        );
+        let mut frames: Vec<Transmit> = (0..C_EV_START_ATTEN_CHAR_INDS)
+            .map(|_| Transmit {
+                destination: BROADCAST_MAC,
+                message: Message::StartAttenCharInd(start.clone()),
+            })
+            .collect();
+        let sounds = MnbcSoundInd::burst(cnf.num_sounds, self.run_id, self.config.pev_id);
+        frames.extend(sounds.map(|sound| Transmit {
+            destination: cnf.msound_target,
+            message: Message::MnbcSoundInd(sound),
+        }));
+        frames
+    }
+
+    fn slac_match_req(&self, selection: &Selection) -> Transmit {
+        let mut req =
+            SlacMatchReq::new(self.config.pev_mac, selection.chosen.evse_mac, self.run_id);
+        req.pev_id = self.config.pev_id;
+        req.evse_id = selection.chosen.evse_id;
+        Transmit {
+            destination: selection.chosen.evse_mac,
+            message: Message::SlacMatchReq(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{SlacMatchCnf, ATTEN_GROUPS};
+
+    const PEV_MAC: MacAddr = [0x02, 0, 0, 0, 0, 0x01];
+    const MODEM_MAC: MacAddr = [0x00, 0xb0, 0x52, 0, 0, 0x01];
+    const NEAR_EVSE: MacAddr = [0x02, 0, 0, 0, 0, 0x02];
+    const FAR_EVSE: MacAddr = [0x02, 0, 0, 0, 0, 0x03];
+
+    fn run_id_of(transmits: &[Transmit]) -> RunId {
+        match transmits[0].message {
+            Message::SlacParmReq(ref req) => req.run_id,
+            ref message => panic!("{:?} instead of CM_SLAC_PARM.REQ", message),
+        }
+    }
+
+    #[test]
+    fn repeats_slac_parm_req() {
+        let mut pev = Pev::new(PevConfig::new(PEV_MAC, MODEM_MAC));
+        let now = Instant::now();
+        let first = pev.start(now);
+        let retry = pev.handle_timeout(now + TT_MATCH_SEQUENCE).unwrap();
+        assert_eq!(retry, first);
+        pev.handle_timeout(now + 2 * TT_MATCH_SEQUENCE).unwrap();
+        assert_eq!(
+            pev.handle_timeout(now + 3 * TT_MATCH_SEQUENCE),
+            Err(SlacError::Timeout {
+                state: "waiting for CM_SLAC_PARM.CNF"
+            })
+        );
+    }
+
+    #[test]
+    fn picks_the_lowest_attenuation() {
+        let mut pev = Pev::new(PevConfig::new(PEV_MAC, MODEM_MAC));
+        let now = Instant::now();
+        let run_id = run_id_of(&pev.start(now));
+        let cnf = Message::SlacParmCnf(SlacParmCnf::new(PEV_MAC, 10, 6, run_id));
+        let sounding = pev.handle(NEAR_EVSE, cnf, now).unwrap();
+        assert_eq!(sounding.len(), usize::from(C_EV_START_ATTEN_CHAR_INDS) + 10);
+        for (evse, attenuation) in [(FAR_EVSE, 30), (NEAR_EVSE, 20)].iter() {
+            let aag = AttenuationGroups([*attenuation; ATTEN_GROUPS]);
+            let ind = AttenCharInd::new(PEV_MAC, run_id, [0; 17], 10, aag);
+            let rsp = pev.handle(*evse, Message::AttenCharInd(ind), now).unwrap();
+            assert_eq!(rsp[0].destination, *evse);
+        }
+
+        let req = pev.handle_timeout(now + TT_EV_ATTEN_RESULTS).unwrap();
+        assert_eq!(req[0].destination, NEAR_EVSE);
+        let req = match &req[0].message {
+            Message::SlacMatchReq(req) => req,
+            message => panic!("{:?} instead of CM_SLAC_MATCH.REQ", message),
+        };
+        let cnf = SlacMatchCnf::new(req, [0; 17], [1; 7], [2; 16]);
+        let set_key = pev
+            .handle(NEAR_EVSE, Message::SlacMatchCnf(cnf), now)
+            .unwrap();
+        assert_eq!(set_key[0].destination, MODEM_MAC);
+        let outcome = pev.outcome().unwrap();
+        assert_eq!(outcome.evse_mac, NEAR_EVSE);
+        assert_eq!(outcome.selection.candidates.len(), 2);
+        assert_eq!(outcome.selection.chosen.average_attenuation, 20.0);
+    }
+}
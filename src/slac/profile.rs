@@ -0,0 +1,90 @@
+//! Attenuation profile aggregation on the EVSE side
+
+use crate::messages::{AttenuationGroups, ATTEN_GROUPS};
+
+/// Per group average of the CM_ATTEN_PROFILE.IND reported for the sounds of
+/// one session
+#[derive(Debug, Clone)]
+pub struct ProfileAggregator {
+    sums: [u32; ATTEN_GROUPS],
+    count: u8,
+}
+
+impl Default for ProfileAggregator {
+    fn default() -> Self {
+        ProfileAggregator {
+            sums: [0; ATTEN_GROUPS],
+            count: 0,
+        }
+    }
+}
+
+impl ProfileAggregator {
+    pub fn new() -> Self {
+        ProfileAggregator::default()
+    }
+
+    pub fn add(&mut self, aag: &AttenuationGroups) {
+        for (sum, group) in self.sums.iter_mut().zip(aag.0.iter()) {
+            *sum += u32::from(*group);
+        }
+        self.count = self.count.saturating_add(1);
+    }
+
+    /// Number of profiles added so far
+    pub fn count(&self) -> u8 {
+        self.count
+    }
+
+    /// The averaged profile, rounded to the nearest dB
+    pub fn profile(&self) -> Option<AttenuationGroups> {
+        if self.count == 0 {
+            return None;
+        }
+        let count = u32::from(self.count);
+        let mut aag = AttenuationGroups::default();
+        for (group, sum) in aag.0.iter_mut().zip(self.sums.iter()) {
+            *group = ((sum + count / 2) / count) as u8;
+        }
+        Some(aag)
+    }
+}
+
+/// Coupling quality of a session, as sent to the PEV in CM_ATTEN_CHAR.IND
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttenuationReport {
+    /// Number of sounds the profile is averaged over
+    pub num_sounds: u8,
+    pub profile: AttenuationGroups,
+    /// Mean over all groups of `profile`, in dB
+    pub mean_attenuation: f32,
+}
+
+impl AttenuationReport {
+    pub fn new(aggregator: &ProfileAggregator) -> Option<Self> {
+        let profile = aggregator.profile()?;
+        Some(AttenuationReport {
+            num_sounds: aggregator.count(),
+            mean_attenuation: profile.mean(),
+            profile,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_rounded() {
+        let mut aggregator = ProfileAggregator::new();
+        assert_eq!(aggregator.profile(), None);
+        assert_eq!(AttenuationReport::new(&aggregator), None);
+        aggregator.add(&AttenuationGroups([10; ATTEN_GROUPS]));
+        aggregator.add(&AttenuationGroups([13; ATTEN_GROUPS]));
+        let report = AttenuationReport::new(&aggregator).unwrap();
+        assert_eq!(report.num_sounds, 2);
+        assert_eq!(report.profile, AttenuationGroups([12; ATTEN_GROUPS]));
+        assert_eq!(report.mean_attenuation, 12.0);
+    }
+}
@@ -0,0 +1,232 @@
+//! CM_SET_KEY exchange with the local modem
+//!
+//! Once matched, both sides program the NMK into their own PLC modem. The
+//! modem answers with CM_SET_KEY.CNF, which has to echo the nonce of the
+//! request. `set_key` runs the whole exchange over a `RawSocket`, for callers
+//! without an event loop of their own.
+
+use std::time::{Duration, Instant};
+
+use super::{SlacError, Transmit};
+use crate::codec::Encode;
+use crate::error::Error;
+use crate::messages::{MacAddr, Message, SetKeyCnf, SetKeyReq, SET_KEY_RESULT_SUCCESS};
+use crate::mme::Mme;
+use crate::socket::RawSocket;
+
+/// Time the local modem gets to confirm the new key, ISO 15118-3 doesn't
+/// define one
+pub const TT_SET_KEY_RESPONSE: Duration = Duration::from_secs(1);
+
+const STATE: &str = "waiting for CM_SET_KEY.CNF";
+
+/// One CM_SET_KEY.REQ waiting for its confirmation
+#[derive(Debug)]
+pub struct SetKeyExchange {
+    modem_mac: MacAddr,
+    my_nonce: [u8; 4],
+    deadline: Instant,
+}
+
+impl SetKeyExchange {
+    /// Starts the exchange, returning the request to send to the modem
+    pub fn start(modem_mac: MacAddr, req: SetKeyReq, now: Instant) -> (Self, Transmit) {
+        let exchange = SetKeyExchange::expect(modem_mac, &req, now);
+        let transmit = Transmit {
+            destination: modem_mac,
+            message: Message::SetKeyReq(req),
+        };
+        (exchange, transmit)
+    }
+
+    /// Waits for the confirmation of a request that was already sent, e.g.
+    /// the one coming out of the EVSE or PEV state machine
+    pub fn expect(modem_mac: MacAddr, req: &SetKeyReq, now: Instant) -> Self {
+        SetKeyExchange {
+            modem_mac,
+            my_nonce: req.my_nonce(),
+            deadline: now + TT_SET_KEY_RESPONSE,
+        }
+    }
+
+    /// Instant at which `handle_timeout` has to be called
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Checks the confirmation, anything else coming in is an error
+    pub fn handle(&self, source: MacAddr, message: Message) -> Result<SetKeyCnf, SlacError> {
+        let cnf = match message {
+            Message::SetKeyCnf(cnf) => cnf,
+            _ => return Err(SlacError::UnexpectedMessage { state: STATE }),
+        };
+        if source != self.modem_mac {
+            return Err(SlacError::UnexpectedSender {
+                expected: self.modem_mac,
+                received: source,
+            });
+        }
+        if cnf.your_nonce != self.my_nonce {
+            return Err(SlacError::NonceMismatch {
+                expected: self.my_nonce,
+                received: cnf.your_nonce,
+            });
+        }
+        if cnf.result != SET_KEY_RESULT_SUCCESS {
+            return Err(SlacError::Refused {
+                message: "CM_SET_KEY.CNF",
+            });
+        }
+        Ok(cnf)
+    }
+
+    pub fn handle_timeout(&self, now: Instant) -> Result<(), SlacError> {
+        if now >= self.deadline {
+            return Err(SlacError::Timeout { state: STATE });
+        }
+        Ok(())
+    }
+}
+
+/// Sends `req` to the modem and blocks until it confirms the key or
+/// `TT_SET_KEY_RESPONSE` runs out. `socket` has to receive HomePlug AV
+/// frames. Other frames, as well as confirmations of other modems or with a
+/// stale nonce, are skipped.
+pub fn set_key(socket: &RawSocket, modem_mac: MacAddr, req: SetKeyReq) -> crate::Result<SetKeyCnf> {
+    let (exchange, transmit) = SetKeyExchange::start(modem_mac, req, Instant::now());
+    let frame = Mme::new(transmit.destination, socket.hw_addr()?, transmit.message).to_vec()?;
+    socket.send_frame(&frame)?;
+    // Largest untagged frame plus an 802.1Q tag
+    let mut buffer = [0; 1522];
+    loop {
+        let now = Instant::now();
+        exchange.handle_timeout(now)?;
+        let remaining = exchange.deadline().saturating_duration_since(now);
+        let len = match socket.recv_timeout(&mut buffer, remaining) {
+            Ok(len) => len,
+            Err(Error::Timeout(_)) => continue,
+            Err(err) => return Err(err),
+        };
+        let mme = match Mme::from_bytes(&buffer[..len]) {
+            Ok(mme) => mme,
+            Err(_) => continue,
+        };
+        match exchange.handle(mme.header.source, mme.message) {
+            Ok(cnf) => return Ok(cnf),
+            Err(SlacError::UnexpectedMessage { .. })
+            | Err(SlacError::UnexpectedSender { .. })
+            | Err(SlacError::NonceMismatch { .. }) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::thread;
+
+    use crate::messages::SET_KEY_RESULT_FAILURE;
+    use crate::mme::ETH_P_HOMEPLUG_AV;
+
+    const MODEM_MAC: MacAddr = [0x00, 0xb0, 0x52, 0, 0, 0x01];
+
+    fn cnf(result: u8, your_nonce: [u8; 4]) -> SetKeyCnf {
+        SetKeyCnf {
+            result,
+            my_nonce: [0; 4],
+            your_nonce,
+            pid: 0x04,
+            prn: [0; 2],
+            pmn: 0,
+            cco_cap: 0,
+        }
+    }
+
+    #[test]
+    fn confirmation_must_echo_the_nonce() {
+        let now = Instant::now();
+        let req = SetKeyReq::new([0; 7], [0; 16]);
+        let (exchange, transmit) = SetKeyExchange::start(MODEM_MAC, req.clone(), now);
+        assert_eq!(transmit.destination, MODEM_MAC);
+        let stale = [!req.my_nonce()[0], 0, 0, 0];
+        assert_eq!(
+            exchange.handle(
+                MODEM_MAC,
+                Message::SetKeyCnf(cnf(SET_KEY_RESULT_SUCCESS, stale))
+            ),
+            Err(SlacError::NonceMismatch {
+                expected: req.my_nonce(),
+                received: stale
+            })
+        );
+        assert_eq!(
+            exchange.handle(
+                MODEM_MAC,
+                Message::SetKeyCnf(cnf(SET_KEY_RESULT_FAILURE, req.my_nonce()))
+            ),
+            Err(SlacError::Refused {
+                message: "CM_SET_KEY.CNF"
+            })
+        );
+        assert!(exchange
+            .handle(
+                MODEM_MAC,
+                Message::SetKeyCnf(cnf(SET_KEY_RESULT_SUCCESS, req.my_nonce()))
+            )
+            .is_ok());
+        assert!(exchange.handle_timeout(exchange.deadline()).is_err());
+    }
+
+    /// Socket receiving the HomePlug AV frames of lo
+    fn open_lo() -> Option<RawSocket> {
+        match RawSocket::open("lo", ETH_P_HOMEPLUG_AV) {
+            Ok(socket) => Some(socket),
+            Err(Error::Os { source, .. }) if source.kind() == io::ErrorKind::PermissionDenied => {
+                None
+            }
+            Err(err) => panic!("opening lo: {}", err),
+        }
+    }
+
+    #[test]
+    fn set_key_skips_stale_confirmations() {
+        let (host, modem) = match (open_lo(), open_lo()) {
+            (Some(host), Some(modem)) => (host, modem),
+            _ => return,
+        };
+        // lo hands every frame to both sockets, the modem answers the request
+        // with a stale confirmation first
+        let modem = thread::spawn(move || {
+            let mut buffer = [0; 1522];
+            loop {
+                let len = modem
+                    .recv_timeout(&mut buffer, Duration::from_secs(1))
+                    .unwrap();
+                if let Ok(Mme {
+                    header,
+                    message: Message::SetKeyReq(req),
+                }) = Mme::from_bytes(&buffer[..len])
+                {
+                    let stale = [!req.my_nonce()[0], 0, 0, 0];
+                    for your_nonce in [stale, req.my_nonce()].iter() {
+                        let cnf = cnf(SET_KEY_RESULT_SUCCESS, *your_nonce);
+                        let reply = Mme::new(header.source, MODEM_MAC, Message::SetKeyCnf(cnf));
+                        modem.send_frame(&reply.to_vec().unwrap()).unwrap();
+                    }
+                    return;
+                }
+            }
+        });
+        let req = SetKeyReq::new([0; 7], [0; 16]);
+        let cnf = set_key(&host, MODEM_MAC, req.clone()).unwrap();
+        assert_eq!(cnf.your_nonce, req.my_nonce());
+        modem.join().unwrap();
+
+        match set_key(&host, MODEM_MAC, req) {
+            Err(Error::Slac(SlacError::Timeout { .. })) => (),
+            other => panic!("{:?} instead of a timeout", other),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! BCB toggle validation on the EVSE side
+//!
+//! When several chargers share a cable bundle the attenuation alone may not
+//! tell which one the PEV is plugged into. With CM_VALIDATE the PEV toggles
+//! its control pilot between state B and C (the Basic Charging Board toggle)
+//! and each EVSE reports how many toggles it saw on its own pilot line.
+
+use std::time::Duration;
+
+/// Access to the control pilot of the EVSE, implemented by the charger
+pub trait BcbToggle {
+    /// Whether the pilot can be watched right now, asked on the first
+    /// CM_VALIDATE.REQ
+    fn ready(&mut self) -> bool;
+
+    /// Starts counting B/C toggles for the next `window`
+    fn start(&mut self, window: Duration);
+
+    /// Number of toggles seen since `start`, asked once the window is over
+    fn toggles(&mut self) -> u8;
+}
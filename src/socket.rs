@@ -0,0 +1,857 @@
+//! AF_PACKET raw sockets
+//!
+//! `RawSocket` owns the file descriptor, it's bound to one interface and one
+//! ethertype when opened and closed when dropped.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bpf::Filter;
+use crate::error::{Error, Result};
+use crate::messages::MacAddr;
+use crate::ring::{RingConfig, RxRing};
+
+pub const ETH_P_ALL: u16 = 0x0003;
+const SIOCGIFFLAGS: libc::Ioctl = 0x8913;
+const SIOCGIFINDEX: libc::Ioctl = 0x8933;
+pub const IFNAMSIZ: usize = 16; // net/if.h
+const UIO_MAXIOV: usize = 1024; // linux/uio.h
+
+// struct ifreq is a name followed by a union, the kernel copies all of it in
+// and back out, so the padding has to be there
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct ifreq_ifindex {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_ifindex: libc::c_int,
+    _pad: [u8; 20],
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct ifreq_ifflags {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_flags: libc::c_short,
+    _pad: [u8; 22],
+}
+
+fn cvt(action: &'static str, res: libc::c_int) -> Result<libc::c_int> {
+    if res == -1 {
+        return Err(Error::os(action));
+    }
+    Ok(res)
+}
+
+fn cvt_size(action: &'static str, res: libc::ssize_t) -> Result<usize> {
+    if res == -1 {
+        return Err(Error::os(action));
+    }
+    Ok(res as usize)
+}
+
+/// Checks `ifname` is a name the kernel accepts for an interface: not empty,
+/// shorter than IFNAMSIZ and without '/', ':' or whitespace
+pub fn validate_ifname(ifname: &str) -> Result<()> {
+    let valid = !ifname.is_empty()
+        && ifname.len() < IFNAMSIZ
+        && ifname != "."
+        && ifname != ".."
+        && !ifname
+            .chars()
+            .any(|c| c == '/' || c == ':' || c.is_whitespace());
+    if !valid {
+        return Err(Error::InvalidInterfaceName(ifname.to_string()));
+    }
+    Ok(())
+}
+
+pub(crate) fn ifr_name(ifname: &str) -> [u8; IFNAMSIZ] {
+    let mut name = [0; IFNAMSIZ];
+    name[..ifname.len()].copy_from_slice(ifname.as_bytes());
+    name
+}
+
+// ENODEV is the kernel telling there's no interface of that name
+fn interface_error(action: &'static str, ifname: &str) -> Error {
+    let source = io::Error::last_os_error();
+    if source.raw_os_error() == Some(libc::ENODEV) {
+        return Error::NoSuchInterface(ifname.to_string());
+    }
+    Error::Os { action, source }
+}
+
+fn ifindex_from_ifname(ifname: &str, sock: RawFd) -> Result<libc::c_int> {
+    let mut ifr = ifreq_ifindex {
+        ifr_name: ifr_name(ifname),
+        ifr_ifindex: 0,
+        _pad: [0; 20],
+    };
+    if unsafe { libc::ioctl(sock, SIOCGIFINDEX, &mut ifr) } == -1 {
+        return Err(interface_error("getting ifindex", ifname));
+    }
+    Ok(ifr.ifr_ifindex)
+}
+
+fn ifflags_from_ifname(ifname: &str, sock: RawFd) -> Result<libc::c_int> {
+    let mut ifr = ifreq_ifflags {
+        ifr_name: ifr_name(ifname),
+        ifr_flags: 0,
+        _pad: [0; 22],
+    };
+    if unsafe { libc::ioctl(sock, SIOCGIFFLAGS, &mut ifr) } == -1 {
+        return Err(interface_error("getting interface flags", ifname));
+    }
+    // The flags are unsigned, the ioctl just happens to use a short
+    Ok(libc::c_int::from(ifr.ifr_flags as u16))
+}
+
+fn sockaddr_ll(ether_type: u16, ifindex: libc::c_int) -> libc::sockaddr_ll {
+    libc::sockaddr_ll {
+        sll_family: libc::AF_PACKET as u16,
+        sll_protocol: ether_type.to_be(),
+        sll_ifindex: ifindex,
+        sll_hatype: 0,
+        sll_pkttype: 0,
+        sll_halen: 0,
+        sll_addr: [0; 8],
+    }
+}
+
+/// Who a received frame was meant for, `sll_pkttype`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    /// Addressed to this host
+    Host,
+    Broadcast,
+    Multicast,
+    /// Addressed to another host, seen in promiscuous mode
+    OtherHost,
+    /// Sent from this host, `ETH_P_ALL` sockets see those too
+    Outgoing,
+    Other(u8),
+}
+
+impl From<u8> for PacketType {
+    fn from(pkttype: u8) -> Self {
+        match pkttype {
+            libc::PACKET_HOST => PacketType::Host,
+            libc::PACKET_BROADCAST => PacketType::Broadcast,
+            libc::PACKET_MULTICAST => PacketType::Multicast,
+            libc::PACKET_OTHERHOST => PacketType::OtherHost,
+            libc::PACKET_OUTGOING => PacketType::Outgoing,
+            other => PacketType::Other(other),
+        }
+    }
+}
+
+/// When a frame arrived, if `RawSocket::enable_timestamps` asked for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timestamps {
+    /// CLOCK_REALTIME when the kernel got the frame from the driver
+    pub software: Option<SystemTime>,
+    /// Raw time of the NIC's hardware clock, only there if the interface
+    /// has been set up for receive timestamping
+    pub hardware: Option<Duration>,
+}
+
+impl Timestamps {
+    /// The software timestamp moved to the clock the SLAC state machines
+    /// run on, for feeding them the arrival of a frame rather than the time
+    /// the receiving thread woke up
+    pub fn arrival(&self) -> Option<Instant> {
+        let age = SystemTime::now()
+            .duration_since(self.software?)
+            .unwrap_or_default();
+        Instant::now().checked_sub(age)
+    }
+}
+
+fn timespec_duration(ts: &libc::timespec) -> Option<Duration> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+// Room for the control messages of a frame, u64 keeps cmsghdr aligned
+type Control = [u64; 16];
+
+/// Picks SCM_TIMESTAMPING out of the control messages `message` was filled
+/// with
+unsafe fn timestamps(message: &libc::msghdr) -> Timestamps {
+    let mut timestamps = Timestamps::default();
+    let mut cmsg = libc::CMSG_FIRSTHDR(message);
+    while let Some(header) = cmsg.as_ref() {
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_TIMESTAMPING {
+            // struct scm_timestamping: software, deprecated, raw hardware
+            let ts = ptr::read_unaligned(libc::CMSG_DATA(header) as *const [libc::timespec; 3]);
+            timestamps.software =
+                timespec_duration(&ts[0]).map(|since| SystemTime::UNIX_EPOCH + since);
+            timestamps.hardware = timespec_duration(&ts[2]);
+        }
+        cmsg = libc::CMSG_NXTHDR(message, header);
+    }
+    timestamps
+}
+
+fn msghdr(
+    iovec: &mut libc::iovec,
+    sockaddr: &mut libc::sockaddr_ll,
+    control: &mut Control,
+) -> libc::msghdr {
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = sockaddr as *mut libc::sockaddr_ll as *mut libc::c_void;
+    message.msg_namelen = mem::size_of_val(sockaddr) as libc::socklen_t;
+    message.msg_iov = iovec;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = mem::size_of_val(control) as _;
+    message
+}
+
+/// What the kernel tells about a received frame besides its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Length on the wire, more than the buffer holds if it was cut short
+    pub len: usize,
+    /// Hardware address of the sender
+    pub source: MacAddr,
+    pub packet_type: PacketType,
+    /// Interface the frame came in on
+    pub ifindex: libc::c_int,
+    /// Ethertype, in host byte order
+    pub protocol: u16,
+    pub timestamps: Timestamps,
+}
+
+impl FrameInfo {
+    fn new(len: usize, message: &libc::msghdr) -> Self {
+        let sockaddr = unsafe { &*(message.msg_name as *const libc::sockaddr_ll) };
+        let mut source = [0; 6];
+        source.copy_from_slice(&sockaddr.sll_addr[..6]);
+        FrameInfo {
+            len,
+            source,
+            packet_type: PacketType::from(sockaddr.sll_pkttype),
+            ifindex: sockaddr.sll_ifindex,
+            protocol: u16::from_be(sockaddr.sll_protocol),
+            timestamps: unsafe { timestamps(message) },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RawSocket {
+    fd: OwnedFd,
+}
+
+impl RawSocket {
+    /// Opens a socket receiving the `ether_type` frames of `ifname`, or all of
+    /// them with `ETH_P_ALL`. The interface has to exist and be up.
+    pub fn open(ifname: &str, ether_type: u16) -> Result<Self> {
+        RawSocket::open_bound(ifname, ether_type, None)
+    }
+
+    /// Like `open`, with `filter` in place before the socket is bound, so not
+    /// a single frame gets past it. An unbound socket doesn't queue anything.
+    pub fn open_with_filter(ifname: &str, ether_type: u16, filter: &Filter) -> Result<Self> {
+        RawSocket::open_bound(ifname, ether_type, Some(filter))
+    }
+
+    fn open_bound(ifname: &str, ether_type: u16, filter: Option<&Filter>) -> Result<Self> {
+        validate_ifname(ifname)?;
+        // Protocol 0 receives nothing until bind names the ethertype
+        let fd = cvt("opening socket", unsafe {
+            libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0)
+        })?;
+        let socket = unsafe { RawSocket::from_raw_fd(fd) };
+        let ifindex = ifindex_from_ifname(ifname, fd)?;
+        if ifflags_from_ifname(ifname, fd)? & libc::IFF_UP == 0 {
+            return Err(Error::InterfaceDown(ifname.to_string()));
+        }
+        if let Some(filter) = filter {
+            socket.attach_filter(filter)?;
+        }
+        let sockaddr = sockaddr_ll(ether_type, ifindex);
+        cvt("binding socket", unsafe {
+            libc::bind(
+                fd,
+                &sockaddr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of_val(&sockaddr) as libc::socklen_t,
+            )
+        })?;
+        Ok(socket)
+    }
+
+    fn local_addr(&self) -> Result<libc::sockaddr_ll> {
+        let mut sockaddr = sockaddr_ll(0, 0);
+        let mut len = mem::size_of_val(&sockaddr) as libc::socklen_t;
+        cvt("getting socket address", unsafe {
+            libc::getsockname(
+                self.as_raw_fd(),
+                &mut sockaddr as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut len,
+            )
+        })?;
+        Ok(sockaddr)
+    }
+
+    /// Index of the interface the socket is bound to
+    pub fn ifindex(&self) -> Result<libc::c_int> {
+        Ok(self.local_addr()?.sll_ifindex)
+    }
+
+    /// Hardware address of the interface the socket is bound to
+    pub fn hw_addr(&self) -> Result<MacAddr> {
+        let sockaddr = self.local_addr()?;
+        let mut hw_addr = [0; 6];
+        hw_addr.copy_from_slice(&sockaddr.sll_addr[..6]);
+        Ok(hw_addr)
+    }
+
+    /// With O_NONBLOCK set, receiving and sending fail with `WouldBlock`
+    /// instead of waiting
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        let flags = cvt("getting file status flags", unsafe {
+            libc::fcntl(self.as_raw_fd(), libc::F_GETFL)
+        })?;
+        let flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        cvt("setting file status flags", unsafe {
+            libc::fcntl(self.as_raw_fd(), libc::F_SETFL, flags)
+        })?;
+        Ok(())
+    }
+
+    /// Replaces the socket filter, frames already queued have been let in by
+    /// the previous one
+    pub fn attach_filter(&self, filter: &Filter) -> Result<()> {
+        let instructions = filter.instructions();
+        let program = libc::sock_fprog {
+            len: instructions.len() as libc::c_ushort,
+            filter: instructions.as_ptr() as *mut libc::sock_filter,
+        };
+        cvt("attaching socket filter", unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &program as *const libc::sock_fprog as *const libc::c_void,
+                mem::size_of_val(&program) as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Lets every frame through again
+    pub fn detach_filter(&self) -> Result<()> {
+        // The value is ignored, but has to be the size of an int
+        let unused: libc::c_int = 0;
+        cvt("detaching socket filter", unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_DETACH_FILTER,
+                &unused as *const libc::c_int as *const libc::c_void,
+                mem::size_of_val(&unused) as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Switches the socket over to a memory mapped TPACKET_V3 receive ring
+    pub fn into_rx_ring(self, config: &RingConfig) -> Result<RxRing> {
+        RxRing::new(self, config)
+    }
+
+    /// Sends a complete Ethernet frame, header included
+    pub fn send_frame(&self, frame: &[u8]) -> Result<usize> {
+        cvt_size("sending frame", unsafe {
+            libc::send(
+                self.as_raw_fd(),
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+            )
+        })
+    }
+
+    /// Sends complete Ethernet frames with as few system calls as sendmmsg
+    /// allows, for bursts like the MNBC sounds. The frames are queued in
+    /// order and leave back to back, `send_frames_paced` spaces them out. An
+    /// error may come after some of them are already out.
+    pub fn send_frames<F: AsRef<[u8]>>(&self, frames: &[F]) -> Result<()> {
+        let mut iovecs: Vec<libc::iovec> = frames
+            .iter()
+            .map(|frame| libc::iovec {
+                iov_base: frame.as_ref().as_ptr() as *mut libc::c_void,
+                iov_len: frame.as_ref().len(),
+            })
+            .collect();
+        let mut messages: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+                message.msg_hdr.msg_iov = iovec;
+                message.msg_hdr.msg_iovlen = 1;
+                message
+            })
+            .collect();
+        let mut sent = 0;
+        while sent < messages.len() {
+            // The kernel takes at most UIO_MAXIOV messages per call
+            let count = (messages.len() - sent).min(UIO_MAXIOV);
+            sent += cvt("sending frames", unsafe {
+                libc::sendmmsg(
+                    self.as_raw_fd(),
+                    messages[sent..].as_mut_ptr(),
+                    count as libc::c_uint,
+                    0,
+                )
+            })? as usize;
+        }
+        Ok(())
+    }
+
+    /// Sends `frames` one by one, `interval` apart, for bursts that have to be
+    /// spaced like the sounds with `TP_EV_BATCH_MSG_INTERVAL`. The frames
+    /// are sent on a fixed schedule from the first one on, so a late wakeup
+    /// doesn't push back the ones after it. A zero `interval` is `send_frames`.
+    pub fn send_frames_paced<F: AsRef<[u8]>>(
+        &self,
+        frames: &[F],
+        interval: Duration,
+    ) -> Result<()> {
+        if interval.is_zero() {
+            return self.send_frames(frames);
+        }
+        let start = Instant::now();
+        for (n, frame) in frames.iter().enumerate() {
+            let due = start + interval * n as u32;
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+            self.send_frame(frame.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until a frame arrives, returning its length. Whatever doesn't
+    /// fit into `buffer` is dropped.
+    pub fn recv_frame(&self, buffer: &mut [u8]) -> Result<usize> {
+        self.recv_with(buffer, 0)
+    }
+
+    /// Like `recv_frame`, failing with `Error::Timeout` if no frame arrives
+    /// within `timeout`
+    pub fn recv_timeout(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.recv_within(timeout, || self.recv_with(buffer, libc::MSG_DONTWAIT))
+    }
+
+    fn recv_with(&self, buffer: &mut [u8], flags: libc::c_int) -> Result<usize> {
+        cvt_size("receiving frame", unsafe {
+            libc::recv(
+                self.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                flags,
+            )
+        })
+    }
+
+    /// Polls until `receive`, which mustn't block, gets a frame or `timeout`
+    /// runs out
+    fn recv_within<T>(
+        &self,
+        timeout: Duration,
+        mut receive: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        // A timeout too long for the clock waits forever
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let millis = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    // Rounded up, poll would spin through the last millisecond otherwise
+                    let millis = (remaining.as_nanos() + 999_999) / 1_000_000;
+                    millis.min(libc::c_int::MAX as u128) as libc::c_int
+                }
+                None => -1,
+            };
+            let mut fd = libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut fd, 1, millis) };
+            if ready == -1 {
+                let source = io::Error::last_os_error();
+                if source.kind() != io::ErrorKind::Interrupted {
+                    return Err(Error::Os {
+                        action: "waiting for a frame",
+                        source,
+                    });
+                }
+            } else if ready > 0 {
+                match receive() {
+                    Err(Error::Os { source, .. }) if source.kind() == io::ErrorKind::WouldBlock => {
+                        // Someone else sharing the socket got the frame first
+                    }
+                    res => return res,
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::Timeout(timeout));
+            }
+        }
+    }
+
+    /// Stamps every frame received from now on with the time the kernel got
+    /// it, and with the NIC's time as well if the interface is set up for
+    /// hardware timestamps
+    pub fn enable_timestamps(&self) -> Result<()> {
+        let flags = libc::SOF_TIMESTAMPING_RX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_SOFTWARE
+            | libc::SOF_TIMESTAMPING_RX_HARDWARE
+            | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+        cvt("enabling timestamps", unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPING,
+                &flags as *const libc::c_uint as *const libc::c_void,
+                mem::size_of_val(&flags) as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Like `recv_frame`, telling where and when the frame came from
+    pub fn recv_frame_from(&self, buffer: &mut [u8]) -> Result<FrameInfo> {
+        self.recv_from_with(buffer, 0)
+    }
+
+    /// Like `recv_frame_from`, failing with `Error::Timeout` if no frame
+    /// arrives within `timeout`
+    pub fn recv_from_timeout(&self, buffer: &mut [u8], timeout: Duration) -> Result<FrameInfo> {
+        self.recv_within(timeout, || self.recv_from_with(buffer, libc::MSG_DONTWAIT))
+    }
+
+    fn recv_from_with(&self, buffer: &mut [u8], flags: libc::c_int) -> Result<FrameInfo> {
+        let mut iovec = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        let mut sockaddr = sockaddr_ll(0, 0);
+        let mut control: Control = [0; 16];
+        let mut message = msghdr(&mut iovec, &mut sockaddr, &mut control);
+        let received = cvt_size("receiving frame", unsafe {
+            libc::recvmsg(self.as_raw_fd(), &mut message, flags | libc::MSG_TRUNC)
+        })?;
+        Ok(FrameInfo::new(received, &message))
+    }
+
+    /// Blocks until a frame arrives, then takes as many of the queued frames
+    /// as there are `buffers` with a single recvmmsg. The info of the n-th
+    /// frame received is the n-th entry and its bytes are in the n-th buffer.
+    pub fn recv_frames<B: AsMut<[u8]>>(&self, buffers: &mut [B]) -> Result<Vec<FrameInfo>> {
+        let count = buffers.len().min(UIO_MAXIOV);
+        let mut iovecs: Vec<libc::iovec> = buffers[..count]
+            .iter_mut()
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut().as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.as_mut().len(),
+            })
+            .collect();
+        let mut sockaddrs = vec![sockaddr_ll(0, 0); count];
+        let mut controls: Vec<Control> = vec![[0; 16]; count];
+        let mut messages: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(sockaddrs.iter_mut())
+            .zip(controls.iter_mut())
+            .map(|((iovec, sockaddr), control)| libc::mmsghdr {
+                msg_hdr: msghdr(iovec, sockaddr, control),
+                msg_len: 0,
+            })
+            .collect();
+        let received = cvt("receiving frames", unsafe {
+            libc::recvmmsg(
+                self.as_raw_fd(),
+                messages.as_mut_ptr(),
+                count as libc::c_uint,
+                // An int on glibc, unsigned on musl
+                (libc::MSG_WAITFORONE | libc::MSG_TRUNC) as _,
+                ptr::null_mut(),
+            )
+        })? as usize;
+        Ok(messages[..received]
+            .iter()
+            .map(|message| FrameInfo::new(message.msg_len as usize, &message.msg_hdr))
+            .collect())
+    }
+}
+
+impl AsRawFd for RawSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl FromRawFd for RawSocket {
+    /// `fd` has to be an AF_PACKET socket, already bound to an interface
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        RawSocket {
+            fd: OwnedFd::from_raw_fd(fd),
+        }
+    }
+}
+
+impl IntoRawFd for RawSocket {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// Socket on lo, every test uses an ethertype of its own so the tests
+    /// running alongside don't see each other's frames
+    fn loopback(ether_type: u16) -> Option<RawSocket> {
+        match RawSocket::open("lo", ether_type) {
+            Ok(socket) => Some(socket),
+            Err(Error::Os { source, .. }) if source.kind() == io::ErrorKind::PermissionDenied => {
+                None
+            }
+            Err(err) => panic!("opening lo: {}", err),
+        }
+    }
+
+    /// A frame to lo's all-zero address, which the kernel takes as addressed
+    /// to this host when it comes back in
+    fn test_frame(ether_type: u16, source: MacAddr, seq: u8) -> [u8; 60] {
+        let mut frame = [0; 60];
+        frame[6..12].copy_from_slice(&source);
+        frame[12..14].copy_from_slice(&ether_type.to_be_bytes());
+        frame[14] = seq;
+        frame
+    }
+
+    #[test]
+    fn frames_come_back_on_lo() {
+        let socket = match loopback(0x88ba) {
+            Some(socket) => socket,
+            None => return,
+        };
+        assert!(socket.ifindex().unwrap() > 0);
+        assert_eq!(socket.hw_addr().unwrap(), [0; 6]);
+
+        let frame = test_frame(0x88ba, [0; 6], 0x5a);
+        assert_eq!(socket.send_frame(&frame).unwrap(), frame.len());
+        let mut buffer = [0; 128];
+        let len = socket.recv_frame(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], &frame[..]);
+    }
+
+    #[test]
+    fn interface_names() {
+        for name in ["eth1", "plc0", "enp0s31f6", "a23456789012345"] {
+            assert!(validate_ifname(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".", "..", "eth0:1", "a/b", "eth 0", "a234567890123456"] {
+            assert!(validate_ifname(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn filter_from_the_start() {
+        let filtered = match RawSocket::open_with_filter("lo", ETH_P_ALL, &Filter::homeplug_av()) {
+            Ok(socket) => socket,
+            Err(Error::Os { source, .. }) if source.kind() == io::ErrorKind::PermissionDenied => {
+                return
+            }
+            Err(err) => panic!("opening lo: {}", err),
+        };
+        let other = 0x88bb;
+        let sender = loopback(other).unwrap();
+        let source = [0x02, 0, 0, 0, 0, 0x03];
+        sender.send_frame(&test_frame(other, source, 0)).unwrap();
+        sender
+            .send_frame(&test_frame(crate::mme::ETH_P_HOMEPLUG_AV, source, 1))
+            .unwrap();
+        // The frame going out comes first, then the one coming back in
+        let mut buffer = [0; 64];
+        for _ in 0..2 {
+            filtered.recv_frame(&mut buffer).unwrap();
+            assert_eq!(buffer[12..15], [0x88, 0xe1, 1]);
+        }
+    }
+
+    #[test]
+    fn frame_bursts() {
+        let ether_type = 0x88b5;
+        let (receiver, sender) = match (loopback(ether_type), loopback(ether_type)) {
+            (Some(receiver), Some(sender)) => (receiver, sender),
+            _ => return,
+        };
+        let source = [0x02, 0, 0, 0, 0, 0x01];
+        let frames: Vec<_> = (0..3)
+            .map(|seq| test_frame(ether_type, source, seq))
+            .collect();
+        sender.send_frames(&frames).unwrap();
+        let mut buffer = [0; 64];
+        for frame in &frames {
+            let len = receiver.recv_frame(&mut buffer).unwrap();
+            assert_eq!(buffer[..len], frame[..]);
+        }
+    }
+
+    #[test]
+    fn paced_frames() {
+        let ether_type = 0x88b8;
+        let (receiver, sender) = match (loopback(ether_type), loopback(ether_type)) {
+            (Some(receiver), Some(sender)) => (receiver, sender),
+            _ => return,
+        };
+        let source = [0x02, 0, 0, 0, 0, 0x04];
+        let frames: Vec<_> = (0..3)
+            .map(|seq| test_frame(ether_type, source, seq))
+            .collect();
+        let interval = Duration::from_millis(20);
+        let start = Instant::now();
+        sender.send_frames_paced(&frames, interval).unwrap();
+        assert!(start.elapsed() >= interval * 2);
+        let mut buffer = [0; 64];
+        for seq in 0..3 {
+            receiver.recv_frame(&mut buffer).unwrap();
+            assert_eq!(buffer[14], seq);
+        }
+    }
+
+    #[test]
+    fn received_frame_info() {
+        let ether_type = 0x88b6;
+        let (receiver, sender) = match (loopback(ether_type), loopback(ether_type)) {
+            (Some(receiver), Some(sender)) => (receiver, sender),
+            _ => return,
+        };
+        let source = [0x02, 0, 0, 0, 0, 0x01];
+        let frames: Vec<_> = (0..3)
+            .map(|seq| test_frame(ether_type, source, seq))
+            .collect();
+        sender.send_frames(&frames).unwrap();
+
+        let mut short = [0; 16];
+        let mut infos = vec![receiver.recv_frame_from(&mut short).unwrap()];
+        assert_eq!(short[..], frames[0][..16]);
+        let mut buffers = [[0; 64]; 4];
+        while infos.len() < 3 {
+            let received = receiver.recv_frames(&mut buffers).unwrap();
+            for (info, buffer) in received.iter().zip(&buffers) {
+                assert_eq!(buffer[..60], frames[infos.len()][..]);
+                infos.push(*info);
+            }
+        }
+
+        let ifindex = receiver.ifindex().unwrap();
+        for info in &infos {
+            // The whole length, even of the frame cut short
+            assert_eq!(info.len, 60);
+            assert_eq!(info.source, source);
+            assert_eq!(info.packet_type, PacketType::Host);
+            assert_eq!(info.protocol, ether_type);
+            assert_eq!(info.ifindex, ifindex);
+        }
+    }
+
+    #[test]
+    fn software_timestamps() {
+        let ether_type = 0x88b9;
+        let (receiver, sender) = match (loopback(ether_type), loopback(ether_type)) {
+            (Some(receiver), Some(sender)) => (receiver, sender),
+            _ => return,
+        };
+        receiver.enable_timestamps().unwrap();
+        let source = [0x02, 0, 0, 0, 0, 0x05];
+        let mut buffer = [0; 64];
+        // The kernel turns receive timestamps on from a workqueue, the first
+        // frames after enabling them may still come without one
+        let stamped = (0..100).find_map(|seq| {
+            sender
+                .send_frame(&test_frame(ether_type, source, seq))
+                .unwrap();
+            let info = receiver.recv_frame_from(&mut buffer).unwrap();
+            assert_eq!(buffer[14], seq);
+            if info.timestamps.software.is_none() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            info.timestamps.software.map(|_| info)
+        });
+        let info = stamped.expect("no timestamped frame after a second");
+        let age = SystemTime::now()
+            .duration_since(info.timestamps.software.unwrap())
+            .unwrap();
+        assert!(age < Duration::from_secs(5));
+        assert!(info.timestamps.arrival().unwrap() <= Instant::now());
+        // lo has no hardware clock
+        assert_eq!(info.timestamps.hardware, None);
+
+        // Once on, the batches are stamped as well
+        sender
+            .send_frame(&test_frame(ether_type, source, 100))
+            .unwrap();
+        let mut buffers = [[0; 64]; 2];
+        let received = receiver.recv_frames(&mut buffers).unwrap();
+        assert_eq!(buffers[0][14], 100);
+        assert!(received[0].timestamps.software.is_some());
+    }
+
+    #[test]
+    fn receive_timeout() {
+        // Nothing sends the OUI extended ethertype on lo
+        let socket = match loopback(0x88b7) {
+            Some(socket) => socket,
+            None => return,
+        };
+        let timeout = Duration::from_millis(50);
+        let mut buffer = [0; 64];
+        let start = Instant::now();
+        match socket.recv_timeout(&mut buffer, timeout) {
+            Err(Error::Timeout(after)) => assert_eq!(after, timeout),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(start.elapsed() >= timeout);
+        assert!(matches!(
+            socket.recv_from_timeout(&mut buffer, timeout),
+            Err(Error::Timeout(_))
+        ));
+        assert!(matches!(
+            socket.recv_timeout(&mut buffer, Duration::ZERO),
+            Err(Error::Timeout(_))
+        ));
+
+        socket.set_nonblocking(true).unwrap();
+        match socket.recv_frame(&mut buffer) {
+            Err(Error::Os { source, .. }) => assert_eq!(source.kind(), io::ErrorKind::WouldBlock),
+            other => panic!("unexpected {:?}", other),
+        }
+        socket.set_nonblocking(false).unwrap();
+
+        // Too long for the clock, waits for the frame instead of panicking
+        let sender = loopback(0x88b7).unwrap();
+        sender
+            .send_frame(&test_frame(0x88b7, [0x02, 0, 0, 0, 0, 0x02], 0))
+            .unwrap();
+        assert_eq!(socket.recv_timeout(&mut buffer, Duration::MAX).unwrap(), 60);
+    }
+}
@@ -0,0 +1,104 @@
+use hex_literal::hex;
+
+use slac::codec::{Decode, DecodeError, Encode};
+use slac::messages::*;
+use slac::mme::{MmType, Mme, MmeError, CM_SET_KEY, CM_SLAC_PARM};
+
+const PEV_MAC: MacAddr = hex!("02 00 00 00 00 01");
+const EVSE_MAC: MacAddr = hex!("02 00 00 00 00 02");
+const RUN_ID: RunId = hex!("01 02 03 04 05 06 07 08");
+const NID: [u8; 7] = hex!("b0 f2 e6 95 66 6b 03");
+const NMK: [u8; 16] = hex!("50 d3 e4 93 3f 85 5b 70 40 78 4d f8 15 aa 8d b7");
+
+fn profile() -> AttenuationGroups {
+    let mut aag = AttenuationGroups::default();
+    for (i, group) in aag.0.iter_mut().enumerate() {
+        *group = i as u8;
+    }
+    aag
+}
+
+fn messages() -> Vec<Message> {
+    let atten_char_ind = AttenCharInd::new(PEV_MAC, RUN_ID, [0; 17], 10, profile());
+    let match_req = SlacMatchReq::new(PEV_MAC, EVSE_MAC, RUN_ID);
+    vec![
+        Message::SlacParmReq(SlacParmReq::new(RUN_ID)),
+        Message::SlacParmReq(SlacParmReq::with_signature(RUN_ID, vec![0, 1])),
+        Message::SlacParmCnf(SlacParmCnf::new(PEV_MAC, 10, 6, RUN_ID)),
+        Message::StartAttenCharInd(StartAttenCharInd::new(PEV_MAC, 10, 6, RUN_ID)),
+        Message::MnbcSoundInd(MnbcSoundInd::new(9, RUN_ID, [0x5a; 16])),
+        Message::AttenProfileInd(AttenProfileInd {
+            pev_mac: PEV_MAC,
+            num_groups: ATTEN_GROUPS as u8,
+            rsvd: 0,
+            aag: profile(),
+        }),
+        Message::AttenCharRsp(AttenCharRsp::new(
+            &atten_char_ind,
+            ATTEN_CHAR_RESULT_SUCCESS,
+        )),
+        Message::AttenCharInd(atten_char_ind),
+        Message::ValidateReq(ValidateReq::new(0)),
+        Message::ValidateCnf(ValidateCnf::new(3, VALIDATE_RESULT_SUCCESS)),
+        Message::SlacMatchCnf(SlacMatchCnf::new(&match_req, [0; 17], NID, NMK)),
+        Message::SlacMatchReq(match_req),
+        Message::SetKeyReq(SetKeyReq::new(NID, NMK)),
+        Message::SetKeyCnf(SetKeyCnf {
+            result: SET_KEY_RESULT_SUCCESS,
+            my_nonce: [1; 4],
+            your_nonce: [2; 4],
+            pid: PID_HLE,
+            prn: [0; 2],
+            pmn: 0,
+            cco_cap: 0,
+        }),
+    ]
+}
+
+#[test]
+fn every_message_round_trips() {
+    for message in messages() {
+        let frame = Mme::new(EVSE_MAC, PEV_MAC, message).to_vec().unwrap();
+        assert!(frame.len() >= 60);
+        assert_eq!(Mme::from_bytes(&frame).unwrap().to_vec().unwrap(), frame);
+    }
+}
+
+#[test]
+fn payload_errors_carry_the_offset() {
+    let match_req = SlacMatchReq::new(PEV_MAC, EVSE_MAC, RUN_ID);
+    let mut payload = match_req.to_vec().unwrap();
+    payload[2] = 0x3f;
+    assert_eq!(
+        SlacMatchReq::decode(&payload),
+        Err(DecodeError::Invalid {
+            field: "MVFLength",
+            offset: 2
+        })
+    );
+    assert_eq!(
+        SlacParmCnf::decode(&[0; 4]),
+        Err(DecodeError::Truncated {
+            offset: 4,
+            needed: 2
+        })
+    );
+}
+
+#[test]
+fn payload_errors_name_the_mmtype() {
+    let mut frame = Mme::new(
+        EVSE_MAC,
+        PEV_MAC,
+        Message::SetKeyReq(SetKeyReq::new(NID, NMK)),
+    )
+    .to_vec()
+    .unwrap();
+    frame[15..17].copy_from_slice(&(CM_SLAC_PARM | 1).to_le_bytes());
+    frame[19] = 0x7f;
+    match Mme::from_bytes(&frame) {
+        Err(MmeError::Payload { mmtype, .. }) => assert_eq!(mmtype.0, CM_SLAC_PARM | 1),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(MmType(CM_SET_KEY).to_string(), "0x6008");
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use hex_literal::hex;
+
+use slac::codec::Encode;
+use slac::key::{self, SecurityLevel};
+use slac::messages::*;
+use slac::mme::Mme;
+use slac::slac::evse::{Evse, EvseConfig};
+use slac::slac::pev::{Pev, PevConfig};
+use slac::slac::{Transmit, C_EV_MATCH_MNBC, TT_EV_ATTEN_RESULTS};
+
+const PEV_MAC: MacAddr = hex!("02 00 00 00 00 01");
+const PEV_MODEM: MacAddr = hex!("00 b0 52 00 00 01");
+const EVSE_MAC: MacAddr = hex!("02 00 00 00 00 02");
+const EVSE_MODEM: MacAddr = hex!("00 b0 52 00 00 02");
+
+/// PEV and EVSE connected through the codec, with the EVSE modem reporting a
+/// profile for every sound it hears
+struct Bench {
+    pev: Pev,
+    evse: Evse,
+    queue: VecDeque<(MacAddr, Transmit)>,
+    set_keys: Vec<(MacAddr, SetKeyReq)>,
+    now: Instant,
+}
+
+impl Bench {
+    fn new(evse: Evse) -> Self {
+        Bench {
+            pev: Pev::new(PevConfig::new(PEV_MAC, PEV_MODEM)),
+            evse,
+            queue: VecDeque::new(),
+            set_keys: Vec::new(),
+            now: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, source: MacAddr, transmits: Vec<Transmit>) {
+        self.queue
+            .extend(transmits.into_iter().map(|transmit| (source, transmit)));
+    }
+
+    fn run(&mut self) {
+        while let Some((source, transmit)) = self.queue.pop_front() {
+            let destination = transmit.destination;
+            let frame = Mme::new(destination, source, transmit.message)
+                .to_vec()
+                .unwrap();
+            let mme = Mme::from_bytes(&frame).unwrap();
+            let message = mme.message;
+            if let Message::SetKeyReq(req) = message {
+                self.set_keys.push((destination, req));
+                continue;
+            }
+            if source == PEV_MAC {
+                let sound = matches!(message, Message::MnbcSoundInd(_));
+                let reply = self.evse.handle(source, message, self.now).unwrap();
+                self.send(EVSE_MAC, reply);
+                if sound {
+                    let profile = AttenProfileInd {
+                        pev_mac: PEV_MAC,
+                        num_groups: ATTEN_GROUPS as u8,
+                        rsvd: 0,
+                        aag: AttenuationGroups([20; ATTEN_GROUPS]),
+                    };
+                    let message = Message::AttenProfileInd(profile);
+                    let reply = self.evse.handle(EVSE_MODEM, message, self.now).unwrap();
+                    self.send(EVSE_MAC, reply);
+                }
+            } else {
+                let reply = self.pev.handle(source, message, self.now).unwrap();
+                self.send(PEV_MAC, reply);
+            }
+        }
+    }
+
+    fn advance(&mut self, by: Duration) {
+        self.now += by;
+        let pev = self.pev.handle_timeout(self.now).unwrap();
+        self.send(PEV_MAC, pev);
+        let evse = self.evse.handle_timeout(self.now).unwrap();
+        self.send(EVSE_MAC, evse);
+        self.run();
+    }
+}
+
+fn evse() -> Evse {
+    let nmk = key::generate_nmk();
+    let nid = key::derive_nid(&nmk, SecurityLevel::SimpleConnect);
+    Evse::new(EvseConfig::new(EVSE_MODEM, nid, nmk))
+}
+
+#[test]
+fn pev_and_evse_match() {
+    let mut bench = Bench::new(evse());
+    let start = bench.pev.start(bench.now);
+    bench.send(PEV_MAC, start);
+    bench.run();
+    assert_eq!(
+        bench.evse.attenuation().unwrap().num_sounds,
+        C_EV_MATCH_MNBC
+    );
+    bench.advance(TT_EV_ATTEN_RESULTS);
+
+    let evse = bench.evse.outcome().unwrap().clone();
+    let pev = bench.pev.outcome().unwrap().clone();
+    assert_eq!(evse.pev_mac, PEV_MAC);
+    assert_eq!(pev.evse_mac, EVSE_MAC);
+    assert_eq!(pev.selection.chosen.average_attenuation, 20.0);
+    assert_eq!((pev.nid, pev.nmk), (evse.nid, evse.nmk));
+    assert_eq!(pev.run_id, evse.run_id);
+
+    // Both modems get the key handed over in CM_SLAC_MATCH.CNF
+    let modems: Vec<MacAddr> = bench.set_keys.iter().map(|(mac, _)| *mac).collect();
+    assert_eq!(modems, vec![EVSE_MODEM, PEV_MODEM]);
+    for (_, req) in &bench.set_keys {
+        assert_eq!((req.nid(), req.new_key()), (evse.nid, evse.nmk));
+    }
+}